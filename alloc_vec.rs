@@ -0,0 +1,367 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::slice;
+
+use super::allocator::{Allocator, Global};
+use super::{FallibleVec, TryReserveError};
+
+/// A vector that grows fallibly through an `Allocator`, such as a
+/// `BumpArena`, rather than always through the global allocator.
+///
+/// It has the same fallible methods as `FallibleVec`, as inherent
+/// methods; `FallibleVec` itself is implemented when the allocator can be
+/// created with `Default`, which `try_with_capacity` needs.
+pub struct AllocVec<T, A: Allocator = Global> {
+    ptr: NonNull<T>,
+    cap: usize,
+    len: usize,
+    alloc: A,
+    marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send, A: Allocator + Send> Send for AllocVec<T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for AllocVec<T, A> {}
+
+impl<T> AllocVec<T> {
+    /// Creates an empty vector on the global allocator. Doesn't allocate.
+    pub fn new() -> Self {
+        AllocVec::new_in(Global)
+    }
+}
+
+impl<T> Default for AllocVec<T> {
+    fn default() -> Self {
+        AllocVec::new()
+    }
+}
+
+impl<T, A: Allocator> AllocVec<T, A> {
+    /// Creates an empty vector that will allocate from |alloc|. Doesn't
+    /// allocate.
+    pub fn new_in(alloc: A) -> Self {
+        AllocVec {
+            ptr: NonNull::dangling(),
+            cap: if mem::size_of::<T>() == 0 { usize::MAX } else { 0 },
+            len: 0,
+            alloc,
+            marker: PhantomData,
+        }
+    }
+
+    /// Creates an empty vector with room for at least |capacity| elements,
+    /// allocated from |alloc|.
+    pub fn try_with_capacity_in(capacity: usize, alloc: A) -> Result<Self, TryReserveError> {
+        let mut vec = AllocVec::new_in(alloc);
+        vec.grow_to(capacity)?;
+        Ok(vec)
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+
+    /// Removes the last element and returns it, or None if empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.len)) })
+    }
+
+    /// Shortens the vector to |len| elements, dropping the rest. Does
+    /// nothing if the vector is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            drop(self.pop());
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    fn grow_to(&mut self, new_cap: usize) -> Result<(), TryReserveError> {
+        if new_cap <= self.cap {
+            return Ok(());
+        }
+        let new_layout = Layout::array::<T>(new_cap)
+            .map_err(|_| TryReserveError::CapacityOverflow)?;
+        let result = if self.cap == 0 {
+            self.alloc.allocate(new_layout)
+        } else {
+            let old_layout = Layout::array::<T>(self.cap).expect("existing layout is valid");
+            unsafe { self.alloc.grow(self.ptr.cast(), old_layout, new_layout) }
+        };
+        let block = result.map_err(|_| TryReserveError::AllocFailed { layout: new_layout })?;
+        self.ptr = block.cast();
+        self.cap = new_cap;
+        Ok(())
+    }
+
+    /// As `FallibleVec::try_push`.
+    pub fn try_push(&mut self, value: T) -> Result<(), TryReserveError> {
+        if self.len == self.cap {
+            let new_cap = if self.cap == 0 { 4 } else {
+                self.cap.checked_mul(2).ok_or(TryReserveError::CapacityOverflow)?
+            };
+            self.grow_to(new_cap)?;
+        }
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// As `FallibleVec::try_reserve`.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if additional <= self.cap - self.len {
+            return Ok(());
+        }
+        let required = self.len.checked_add(additional).ok_or(TryReserveError::CapacityOverflow)?;
+        let new_cap = cmp::max(required, self.cap.saturating_mul(2));
+        match self.grow_to(new_cap) {
+            // Doubling may overflow, or exhaust the allocator, even when
+            // the requested capacity doesn't.
+            Err(_) if new_cap > required => self.grow_to(required),
+            result => result,
+        }
+    }
+
+    /// As `FallibleVec::try_reserve_exact`.
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let required = self.len.checked_add(additional).ok_or(TryReserveError::CapacityOverflow)?;
+        self.grow_to(required)
+    }
+
+    /// As `FallibleVec::try_reserve_bounded`.
+    pub fn try_reserve_bounded(&mut self, additional: usize, max_trusted: usize) -> Result<(), TryReserveError> {
+        self.try_reserve(cmp::min(additional, max_trusted))
+    }
+
+    /// As `FallibleVec::try_extend_from_slice`.
+    pub fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> where T: Clone {
+        self.try_reserve(other.len())?;
+        for item in other {
+            // Reserved above, so this can't fail.
+            self.try_push(item.clone())?;
+        }
+        Ok(())
+    }
+
+    /// As `FallibleVec::try_extend`.
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        let iter = iter.into_iter();
+        self.try_reserve(iter.size_hint().0)?;
+        for item in iter {
+            self.try_push(item)?;
+        }
+        Ok(())
+    }
+
+    /// As `FallibleVec::try_insert`.
+    pub fn try_insert(&mut self, index: usize, value: T) -> Result<(), TryReserveError> {
+        let len = self.len;
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        self.try_reserve(1)?;
+        unsafe {
+            let p = self.ptr.as_ptr().add(index);
+            ptr::copy(p, p.add(1), len - index);
+            ptr::write(p, value);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// As `FallibleVec::try_resize`.
+    pub fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError> where T: Clone {
+        self.try_resize_with(new_len, || value.clone())
+    }
+
+    /// As `FallibleVec::try_resize_with`.
+    pub fn try_resize_with<F: FnMut() -> T>(&mut self, new_len: usize, mut f: F) -> Result<(), TryReserveError> {
+        if new_len > self.len {
+            self.try_reserve(new_len - self.len)?;
+            while self.len < new_len {
+                self.try_push(f())?;
+            }
+        } else {
+            self.truncate(new_len);
+        }
+        Ok(())
+    }
+
+    /// As `FallibleVec::try_into_boxed_slice`. The elements are moved
+    /// into a new allocation from the global allocator.
+    pub fn try_into_boxed_slice(mut self) -> Result<Box<[T]>, TryReserveError> {
+        let mut vec: Vec<T> = FallibleVec::try_with_capacity(self.len)?;
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), vec.as_mut_ptr(), self.len);
+            vec.set_len(self.len);
+        }
+        // The elements now belong to |vec|.
+        self.len = 0;
+        FallibleVec::try_into_boxed_slice(vec)
+    }
+}
+
+impl<T, A: Allocator> Drop for AllocVec<T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len));
+            if self.cap != 0 && mem::size_of::<T>() != 0 {
+                let layout = Layout::array::<T>(self.cap).expect("existing layout is valid");
+                self.alloc.deallocate(self.ptr.cast(), layout);
+            }
+        }
+    }
+}
+
+impl<T, A: Allocator> Deref for AllocVec<T, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T, A: Allocator> DerefMut for AllocVec<T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for AllocVec<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, A: Allocator + Default> FallibleVec<T> for AllocVec<T, A> {
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        AllocVec::try_with_capacity_in(capacity, A::default())
+    }
+
+    fn try_push(&mut self, value: T) -> Result<(), TryReserveError> {
+        AllocVec::try_push(self, value)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        AllocVec::try_reserve(self, additional)
+    }
+
+    fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        AllocVec::try_reserve_exact(self, additional)
+    }
+
+    fn try_reserve_bounded(&mut self, additional: usize, max_trusted: usize) -> Result<(), TryReserveError> {
+        AllocVec::try_reserve_bounded(self, additional, max_trusted)
+    }
+
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> where T: Clone {
+        AllocVec::try_extend_from_slice(self, other)
+    }
+
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        AllocVec::try_extend(self, iter)
+    }
+
+    fn try_insert(&mut self, index: usize, value: T) -> Result<(), TryReserveError> {
+        AllocVec::try_insert(self, index, value)
+    }
+
+    fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError> where T: Clone {
+        AllocVec::try_resize(self, new_len, value)
+    }
+
+    fn try_resize_with<F: FnMut() -> T>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError> {
+        AllocVec::try_resize_with(self, new_len, f)
+    }
+
+    fn try_into_boxed_slice(self) -> Result<Box<[T]>, TryReserveError> {
+        AllocVec::try_into_boxed_slice(self)
+    }
+}
+
+#[test]
+fn global() {
+    let mut vec: AllocVec<String> = FallibleVec::try_with_capacity(2).unwrap();
+    for word in "the quick brown fox".split(' ') {
+        vec.try_push(word.to_string()).unwrap();
+    }
+    vec.try_insert(1, "very".to_string()).unwrap();
+    assert_eq!(vec.as_slice(), ["the", "very", "quick", "brown", "fox"]);
+    vec.try_resize(7, "!".to_string()).unwrap();
+    vec.truncate(6);
+    assert_eq!(vec.pop().as_deref(), Some("!"));
+    let boxed = vec.try_into_boxed_slice().unwrap();
+    assert_eq!(boxed.len(), 5);
+
+    let mut vec: AllocVec<u64> = AllocVec::new();
+    assert!(vec.try_reserve(usize::MAX).is_err());
+    let mut units: AllocVec<()> = AllocVec::new();
+    units.try_extend(std::iter::repeat_n((), 1000)).unwrap();
+    assert_eq!(units.len(), 1000);
+}
+
+#[test]
+fn bump_arena() {
+    use super::BumpArena;
+
+    let arena = BumpArena::try_with_capacity(1024).unwrap();
+    {
+        let mut a: AllocVec<u32, &BumpArena> = AllocVec::new_in(&arena);
+        a.try_extend(0..100).unwrap();
+        // The newest block grows in place.
+        assert_eq!(arena.used(), a.capacity() * 4);
+
+        let mut b: AllocVec<u8, &BumpArena> = AllocVec::try_with_capacity_in(16, &arena).unwrap();
+        b.try_extend_from_slice(b"moov").unwrap();
+        assert_eq!(a.iter().sum::<u32>(), 4950);
+        assert_eq!(&*b, b"moov");
+
+        // Exhausting the arena is an error, not an abort.
+        assert!(a.try_reserve(1000).is_err());
+        assert_eq!(a.len(), 100);
+    }
+
+    #[repr(align(64))]
+    struct Aligned(#[allow(dead_code)] u8);
+    let mut aligned = AllocVec::new_in(&arena);
+    aligned.try_push(Aligned(1)).unwrap();
+    assert_eq!(aligned.as_ptr() as usize % 64, 0);
+}
+
+#[test]
+fn falls_back_to_exact_growth() {
+    use super::BumpArena;
+
+    let arena = BumpArena::try_with_capacity(64).unwrap();
+    let mut vec: AllocVec<u8, &BumpArena> = AllocVec::try_with_capacity_in(40, &arena).unwrap();
+    vec.try_extend_from_slice(&[0; 40]).unwrap();
+    // Doubling to 80 bytes doesn't fit in the arena, but 50 does.
+    vec.try_reserve(10).unwrap();
+    assert_eq!(vec.capacity(), 50);
+}
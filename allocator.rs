@@ -0,0 +1,128 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A stable stand-in for the unstable `core::alloc::Allocator` trait, so
+//! that `AllocVec` can grow through something other than the global
+//! allocator.
+
+use alloc::alloc::{self as heap, Layout};
+use core::fmt;
+use core::ptr::{self, NonNull};
+
+use super::{try_alloc, try_realloc};
+
+/// The error returned by an `Allocator` that can't satisfy a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AllocError {}
+
+/// An allocator, with the same shape as the unstable
+/// `core::alloc::Allocator`, so implementations can move to that trait
+/// once it is stable.
+///
+/// # Safety
+///
+/// Memory returned by `allocate` or `grow` must stay valid, and must not
+/// be handed out again, until it is passed to `deallocate` or `grow`, or
+/// until the allocator (and every reference to it) is gone. Blocks must
+/// be at least as large as requested and aligned as requested.
+pub unsafe trait Allocator {
+    /// Allocates a block for |layout|, which may be larger than asked.
+    /// Zero-sized layouts must be supported.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// Frees a block.
+    ///
+    /// # Safety
+    ///
+    /// |ptr| must have been returned by this allocator for |layout|.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Moves a block to one at least as large as |new_layout|, keeping its
+    /// contents. On failure the old block is left untouched. The default
+    /// implementation allocates, copies and frees.
+    ///
+    /// # Safety
+    ///
+    /// |ptr| must have been returned by this allocator for |old_layout|,
+    /// and |new_layout| must be at least as large.
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout)
+        -> Result<NonNull<[u8]>, AllocError>
+    {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
+    }
+}
+
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout)
+        -> Result<NonNull<[u8]>, AllocError>
+    {
+        (**self).grow(ptr, old_layout, new_layout)
+    }
+}
+
+/// A dangling but well-aligned pointer, for zero-sized blocks.
+pub(crate) fn dangling(layout: Layout) -> NonNull<u8> {
+    // The alignment is a non-zero power of two, so it is a valid,
+    // suitably aligned address.
+    unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+}
+
+/// The global allocator, through the same path as `FallibleVec`, so
+/// allocation limits, statistics and fault injection all apply.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = if layout.size() == 0 {
+            dangling(layout)
+        } else {
+            NonNull::new(try_alloc(layout, false).map_err(|_| AllocError)?).expect("non-null")
+        };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            heap::dealloc(ptr.as_ptr(), layout)
+        }
+    }
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout)
+        -> Result<NonNull<[u8]>, AllocError>
+    {
+        if old_layout.size() == 0 || old_layout.align() != new_layout.align() {
+            let new_ptr = self.allocate(new_layout)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+            self.deallocate(ptr, old_layout);
+            return Ok(new_ptr);
+        }
+        let new_ptr = try_realloc(ptr.as_ptr(), old_layout, new_layout).map_err(|_| AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(NonNull::new(new_ptr).expect("non-null"), new_layout.size()))
+    }
+}
@@ -0,0 +1,201 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp;
+use core::ptr::{self, NonNull};
+use core::slice;
+
+use super::allocator::{AllocError, Allocator};
+use super::{AllocVec, BumpArena, FallibleVec, TryReserveError};
+
+/// A vector whose storage lives in a `FallibleArena`.
+pub type ArenaVec<'a, T> = AllocVec<T, &'a FallibleArena>;
+
+const FIRST_CHUNK_SIZE: usize = 4096;
+const MAX_CHUNK_SIZE: usize = 1 << 20;
+
+/// An arena for scratch memory that all dies together, such as the many
+/// small vectors created while parsing one `moov`.
+///
+/// Memory is handed out from a list of `BumpArena` chunks, each allocated
+/// fallibly and twice the size of the previous one (up to 1 MiB, or more
+/// for a single large request). Running out of memory, or past the
+/// optional limit, is reported as an error. Everything is freed at once
+/// when the arena is dropped; destructors of values allocated in it are
+/// not run.
+pub struct FallibleArena {
+    chunks: RefCell<Vec<BumpArena>>,
+    limit: usize,
+}
+
+impl Default for FallibleArena {
+    fn default() -> Self {
+        FallibleArena::new()
+    }
+}
+
+impl FallibleArena {
+    /// Creates an empty arena with no limit. Doesn't allocate.
+    pub fn new() -> FallibleArena {
+        FallibleArena::with_limit(usize::MAX)
+    }
+
+    /// Creates an empty arena whose chunks may total at most |limit|
+    /// bytes. Doesn't allocate.
+    pub fn with_limit(limit: usize) -> FallibleArena {
+        FallibleArena { chunks: RefCell::new(Vec::new()), limit }
+    }
+
+    /// The total size of the chunks allocated so far, in bytes.
+    pub fn allocated_bytes(&self) -> usize {
+        self.chunks.borrow().iter().map(BumpArena::capacity).sum()
+    }
+
+    /// Moves |value| into the arena and returns a reference to it.
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc<T>(&self, value: T) -> Result<&mut T, TryReserveError> {
+        let layout = Layout::new::<T>();
+        let ptr = self.allocate(layout)
+            .map_err(|_| TryReserveError::AllocFailed { layout })?
+            .cast::<T>();
+        unsafe {
+            ptr::write(ptr.as_ptr(), value);
+            Ok(&mut *ptr.as_ptr())
+        }
+    }
+
+    /// Copies |src| into the arena and returns a reference to the copy.
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc_slice_copy<T: Copy>(&self, src: &[T]) -> Result<&mut [T], TryReserveError> {
+        let layout = Layout::array::<T>(src.len())
+            .map_err(|_| TryReserveError::CapacityOverflow)?;
+        let ptr = self.allocate(layout)
+            .map_err(|_| TryReserveError::AllocFailed { layout })?
+            .cast::<T>();
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr.as_ptr(), src.len());
+            Ok(slice::from_raw_parts_mut(ptr.as_ptr(), src.len()))
+        }
+    }
+
+    /// Creates an empty vector that grows inside the arena.
+    pub fn vec<T>(&self) -> ArenaVec<'_, T> {
+        AllocVec::new_in(self)
+    }
+
+    /// Frees everything allocated so far, keeping the newest (largest)
+    /// chunk for reuse.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        if let Some(mut last) = chunks.pop() {
+            last.reset();
+            chunks.clear();
+            chunks.push(last);
+        }
+    }
+
+    /// Adds a chunk big enough for |layout|.
+    fn try_add_chunk(&self, layout: Layout) -> Result<(), AllocError> {
+        let mut chunks = self.chunks.borrow_mut();
+        let allocated: usize = chunks.iter().map(BumpArena::capacity).sum();
+        let next = chunks.last().map_or(FIRST_CHUNK_SIZE, |c| cmp::min(c.capacity() * 2, MAX_CHUNK_SIZE));
+        // Leave room to align the block within the chunk.
+        let needed = layout.size().checked_add(layout.align()).ok_or(AllocError)?;
+        // Trim the usual chunk size to what the limit has left, but never
+        // below what this request needs.
+        let size = cmp::max(next, needed);
+        let size = cmp::max(needed, cmp::min(size, self.limit.saturating_sub(allocated)));
+        if allocated.checked_add(size).is_none_or(|total| total > self.limit) {
+            return Err(AllocError);
+        }
+        FallibleVec::try_reserve(&mut *chunks, 1).map_err(|_| AllocError)?;
+        chunks.push(BumpArena::try_with_capacity(size).map_err(|_| AllocError)?);
+        Ok(())
+    }
+}
+
+unsafe impl Allocator for FallibleArena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if let Some(chunk) = self.chunks.borrow().last() {
+            if let Ok(block) = chunk.allocate(layout) {
+                return Ok(block);
+            }
+        }
+        self.try_add_chunk(layout)?;
+        self.chunks.borrow().last().expect("just added").allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if let Some(chunk) = self.chunks.borrow().last() {
+            if chunk.contains(ptr) {
+                chunk.deallocate(ptr, layout);
+            }
+        }
+    }
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout)
+        -> Result<NonNull<[u8]>, AllocError>
+    {
+        if let Some(chunk) = self.chunks.borrow().last() {
+            if chunk.contains(ptr) {
+                if let Ok(block) = chunk.grow(ptr, old_layout, new_layout) {
+                    return Ok(block);
+                }
+            }
+        }
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+        Ok(new_ptr)
+    }
+}
+
+#[test]
+fn many_small_allocations() {
+    let arena = FallibleArena::new();
+    let mut refs = Vec::new();
+    for i in 0..10_000u64 {
+        refs.push(arena.try_alloc(i).unwrap());
+    }
+    assert!(refs.iter().enumerate().all(|(i, r)| **r == i as u64));
+    *refs[5] = 50;
+    assert_eq!(*refs[5], 50);
+    assert!(arena.allocated_bytes() >= 80_000);
+
+    let copy = arena.try_alloc_slice_copy(b"stsz").unwrap();
+    copy[0] = b'S';
+    assert_eq!(copy, b"Stsz");
+    assert!(arena.try_alloc_slice_copy::<u8>(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn arena_vecs() {
+    let arena = FallibleArena::new();
+    let mut a = arena.vec();
+    let mut b = arena.vec();
+    for i in 0..1000u32 {
+        a.try_push(i).unwrap();
+        b.try_push(i * 2).unwrap();
+    }
+    assert!(a.iter().zip(b.iter()).all(|(x, y)| x * 2 == *y));
+}
+
+#[test]
+fn exhaustion_is_an_error() {
+    let mut arena = FallibleArena::with_limit(10_000);
+    let mut vec: ArenaVec<u8> = arena.vec();
+    vec.try_resize(8000, 1).unwrap();
+    match vec.try_reserve(8000) {
+        Err(TryReserveError::AllocFailed { .. }) => (),
+        r => panic!("the arena should be exhausted, got {:?}", r),
+    }
+    assert!(arena.try_alloc([0u8; 4096]).is_err());
+    assert!(arena.allocated_bytes() <= 10_000);
+    drop(vec);
+
+    arena.reset();
+    assert!(arena.try_alloc([0u8; 4096]).is_ok());
+}
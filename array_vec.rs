@@ -0,0 +1,263 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+
+use super::{FallibleVec, TryReserveError};
+
+/// A vector with inline storage for up to |N| elements, which never
+/// allocates. Growing it past |N| fails with
+/// `TryReserveError::CapacityOverflow`, so code generic over
+/// `FallibleVec` handles a full `ArrayVec` like a failed allocation.
+pub struct ArrayVec<T, const N: usize> {
+    buf: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    /// Creates an empty vector.
+    pub fn new() -> Self {
+        ArrayVec {
+            // An array of uninitialized elements needs no initialization.
+            buf: unsafe { MaybeUninit::uninit().assume_init() },
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+
+    /// Removes the last element and returns it, or None if empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.buf[self.len].as_ptr().read() })
+    }
+
+    /// Shortens the vector to |len| elements, dropping the rest. Does
+    /// nothing if the vector is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            drop(self.pop());
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    fn check_room(&self, additional: usize) -> Result<(), TryReserveError> {
+        match self.len.checked_add(additional) {
+            Some(required) if required <= N => Ok(()),
+            _ => Err(TryReserveError::CapacityOverflow),
+        }
+    }
+
+    fn push_unchecked(&mut self, value: T) {
+        debug_assert!(self.len < N);
+        self.buf[self.len] = MaybeUninit::new(value);
+        self.len += 1;
+    }
+}
+
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self {
+        ArrayVec::new()
+    }
+}
+
+impl<T, const N: usize> FallibleVec<T> for ArrayVec<T, N> {
+    #[inline]
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        if capacity > N {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+        Ok(ArrayVec::new())
+    }
+
+    #[inline]
+    fn try_push(&mut self, value: T) -> Result<(), TryReserveError> {
+        self.check_room(1)?;
+        self.push_unchecked(value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.check_room(additional)
+    }
+
+    #[inline]
+    fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.check_room(additional)
+    }
+
+    #[inline]
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> where T: Clone {
+        self.check_room(other.len())?;
+        for item in other {
+            self.push_unchecked(item.clone());
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        let iter = iter.into_iter();
+        self.check_room(iter.size_hint().0)?;
+        for item in iter {
+            self.try_push(item)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_insert(&mut self, index: usize, value: T) -> Result<(), TryReserveError> {
+        let len = self.len;
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        self.check_room(1)?;
+        unsafe {
+            let p = self.buf.as_mut_ptr().add(index);
+            ptr::copy(p, p.add(1), len - index);
+            p.write(MaybeUninit::new(value));
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError> where T: Clone {
+        self.try_resize_with(new_len, || value.clone())
+    }
+
+    #[inline]
+    fn try_resize_with<F: FnMut() -> T>(&mut self, new_len: usize, mut f: F) -> Result<(), TryReserveError> {
+        if new_len > N {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+        self.truncate(new_len);
+        while self.len < new_len {
+            self.push_unchecked(f());
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_into_boxed_slice(mut self) -> Result<Box<[T]>, TryReserveError> {
+        let mut vec: Vec<T> = FallibleVec::try_with_capacity(self.len)?;
+        unsafe {
+            ptr::copy_nonoverlapping(self.buf.as_ptr() as *const T, vec.as_mut_ptr(), self.len);
+            vec.set_len(self.len);
+        }
+        // The elements now belong to |vec|.
+        self.len = 0;
+        FallibleVec::try_into_boxed_slice(vec)
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T, const N: usize> Deref for ArrayVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.buf.as_ptr() as *const T, self.len) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.buf.as_mut_ptr() as *mut T, self.len) }
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for ArrayVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Generic parsing code of the kind `ArrayVec` is meant to share with
+/// `Vec`.
+#[cfg(test)]
+fn read_entries<V: FallibleVec<u32> + Default>(data: &[u8]) -> Result<V, TryReserveError> {
+    let mut entries = V::default();
+    for chunk in data.chunks(4) {
+        entries.try_push(u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))?;
+    }
+    Ok(entries)
+}
+
+#[test]
+fn generic_over_storage() {
+    let data = [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3];
+    let heap: Vec<u32> = read_entries(&data).unwrap();
+    let inline: ArrayVec<u32, 4> = read_entries(&data).unwrap();
+    assert_eq!(heap, &*inline);
+    assert_eq!(read_entries::<ArrayVec<u32, 2>>(&data).unwrap_err(), TryReserveError::CapacityOverflow);
+}
+
+#[test]
+fn capacity_errors() {
+    let mut vec: ArrayVec<String, 3> = FallibleVec::try_with_capacity(3).unwrap();
+    assert!(<ArrayVec<String, 3> as FallibleVec<String>>::try_with_capacity(4).is_err());
+    vec.try_extend_from_slice(&["a".to_string(), "c".to_string()]).unwrap();
+    vec.try_insert(1, "b".to_string()).unwrap();
+    assert_eq!(vec.as_slice(), ["a", "b", "c"]);
+    assert!(vec.is_full());
+    assert_eq!(vec.try_push("d".to_string()), Err(TryReserveError::CapacityOverflow));
+    assert!(vec.try_insert(0, "d".to_string()).is_err());
+    assert!(vec.try_reserve(usize::MAX).is_err());
+    assert_eq!(vec.len(), 3);
+
+    vec.try_resize(1, String::new()).unwrap();
+    let mut vec2: ArrayVec<u32, 4> = ArrayVec::new();
+    assert!(vec2.try_extend(0..10).is_err());
+    assert_eq!(vec2.len(), 0, "the lower size_hint is checked up front");
+    assert!(vec2.try_extend((0..10).filter(|_| true)).is_err());
+    assert_eq!(vec2.as_slice(), [0, 1, 2, 3]);
+    vec2.try_resize_with(2, || 0).unwrap();
+    assert_eq!(&*vec2.try_into_boxed_slice().unwrap(), [0, 1]);
+}
+
+#[test]
+fn drops_elements() {
+    use alloc::rc::Rc;
+
+    let rc = Rc::new(());
+    let mut vec: ArrayVec<Rc<()>, 4> = ArrayVec::new();
+    vec.try_resize(3, rc.clone()).unwrap();
+    assert_eq!(Rc::strong_count(&rc), 4);
+    drop(vec.pop());
+    assert_eq!(Rc::strong_count(&rc), 3);
+    let boxed = vec.try_into_boxed_slice().unwrap();
+    assert_eq!(Rc::strong_count(&rc), 3);
+    drop(boxed);
+    assert_eq!(Rc::strong_count(&rc), 1);
+}
@@ -0,0 +1,47 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Times `n` sequential one-element reserves, the pattern used when
+//! reading sample tables entry by entry. With amortized growth the cost
+//! per reserve stays flat as `n` grows. Exact growth reallocates on every
+//! reserve, so its cost depends on how often the allocator can grow the
+//! buffer in place.
+//!
+//! Run with `cargo bench`.
+
+extern crate mp4parse_fallible;
+
+use mp4parse_fallible::FallibleVec;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn sequential_reserves(n: usize, exact: bool) -> Duration {
+    let start = Instant::now();
+    let mut vec: Vec<u32> = Vec::new();
+    for i in 0..n {
+        if exact {
+            FallibleVec::try_reserve_exact(&mut vec, 1).unwrap();
+        } else {
+            FallibleVec::try_reserve(&mut vec, 1).unwrap();
+        }
+        vec.push(i as u32);
+    }
+    black_box(&vec);
+    start.elapsed()
+}
+
+fn report(name: &str, n: usize, elapsed: Duration) {
+    let per_op = elapsed.as_secs_f64() * 1e9 / n as f64;
+    println!("{:<18} n = {:>8}  total {:>10.3} ms  {:>8.2} ns/reserve",
+             name, n, elapsed.as_secs_f64() * 1e3, per_op);
+}
+
+fn main() {
+    for &n in &[1_000, 10_000, 100_000, 1_000_000] {
+        report("try_reserve", n, sequential_reserves(n, false));
+    }
+    for &n in &[1_000, 10_000, 100_000] {
+        report("try_reserve_exact", n, sequential_reserves(n, true));
+    }
+}
@@ -0,0 +1,118 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use core::ptr::{self, NonNull};
+
+use super::{TryReserveError, try_alloc};
+
+/// Types for which a value with all bits set to zero is valid, which is
+/// what makes `try_new_zeroed` and `try_new_zeroed_slice` safe.
+///
+/// # Safety
+///
+/// Implementing this for a type with an invalid all-zero representation
+/// (references, `NonZero*`, most enums) is undefined behaviour.
+pub unsafe trait Zeroable {}
+
+macro_rules! impl_zeroable {
+    ($($t:ty)*) => {
+        $(unsafe impl Zeroable for $t {})*
+    }
+}
+
+impl_zeroable! {
+    bool char
+    u8 u16 u32 u64 u128 usize
+    i8 i16 i32 i64 i128 isize
+    f32 f64
+}
+
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+
+/// Moves |value| into a new `Box`, like `Box::new`. Returns Err(_) if
+/// the allocation fails.
+pub fn try_box_new<T>(value: T) -> Result<Box<T>, TryReserveError> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(Box::new(value));
+    }
+    let ptr = try_alloc(layout, false)? as *mut T;
+    unsafe {
+        ptr::write(ptr, value);
+        Ok(Box::from_raw(ptr))
+    }
+}
+
+/// Allocates a zero-initialized `T` directly on the heap. Unlike
+/// `try_box_new`, the value is never built on the stack first, so this
+/// is suitable for large buffers such as `[u8; 1 << 20]`. Returns Err(_)
+/// if the allocation fails.
+pub fn try_new_zeroed<T: Zeroable>() -> Result<Box<T>, TryReserveError> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(unsafe { Box::from_raw(NonNull::dangling().as_ptr()) });
+    }
+    let ptr = try_alloc(layout, true)? as *mut T;
+    Ok(unsafe { Box::from_raw(ptr) })
+}
+
+/// Allocates a zero-initialized slice of |len| elements. Returns Err(_)
+/// on lack of memory or if the size in bytes overflows.
+pub fn try_new_zeroed_slice<T: Zeroable>(len: usize) -> Result<Box<[T]>, TryReserveError> {
+    let layout = Layout::array::<T>(len).map_err(|_| TryReserveError::CapacityOverflow)?;
+    let ptr = if layout.size() == 0 {
+        NonNull::dangling().as_ptr()
+    } else {
+        try_alloc(layout, true)? as *mut T
+    };
+    Ok(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)) })
+}
+
+#[test]
+fn try_box_new_values() {
+    let boxed = try_box_new(42u64).unwrap();
+    assert_eq!(*boxed, 42);
+    let boxed = try_box_new(vec![1, 2, 3]).unwrap();
+    assert_eq!(*boxed, [1, 2, 3]);
+    let boxed = try_box_new(()).unwrap();
+    assert_eq!(*boxed, ());
+}
+
+#[test]
+fn zeroed() {
+    let scratch: Box<[u8; 1 << 20]> = try_new_zeroed().unwrap();
+    assert!(scratch.iter().all(|&b| b == 0));
+
+    let slice: Box<[u32]> = try_new_zeroed_slice(1000).unwrap();
+    assert_eq!(slice.len(), 1000);
+    assert!(slice.iter().all(|&n| n == 0));
+
+    let empty: Box<[u32]> = try_new_zeroed_slice(0).unwrap();
+    assert!(empty.is_empty());
+
+    assert_eq!(try_new_zeroed_slice::<u32>(usize::MAX).err(),
+               Some(TryReserveError::CapacityOverflow));
+    match try_new_zeroed_slice::<u8>(usize::MAX / 4) {
+        Err(TryReserveError::AllocFailed { .. }) => (),
+        r => panic!("it should be OOM, got {:?}", r.map(|s| s.len())),
+    }
+}
+
+#[test]
+fn try_into_boxed_slice() {
+    use super::FallibleVec;
+
+    let mut vec: Vec<u32> = FallibleVec::try_with_capacity(100).unwrap();
+    vec.extend_from_slice(&[1, 2, 3]);
+    let boxed = FallibleVec::try_into_boxed_slice(vec).unwrap();
+    assert_eq!(&*boxed, &[1, 2, 3]);
+
+    let vec: Vec<u32> = FallibleVec::try_with_capacity(100).unwrap();
+    assert!(FallibleVec::try_into_boxed_slice(vec).unwrap().is_empty());
+
+    let boxed = FallibleVec::try_into_boxed_slice(vec![(); 5]).unwrap();
+    assert_eq!(boxed.len(), 5);
+}
@@ -0,0 +1,211 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use super::{TryReserveError, try_extend_vec, vec_try_push, vec_try_reserve};
+
+/// A limit on the number of bytes a parse may allocate.
+///
+/// On 64-bit systems with overcommit, malloc rarely returns null, so a
+/// huge count from a hostile file "succeeds" and the process is killed
+/// later. Growing vectors through an `AllocBudget` instead fails with
+/// `TryReserveError::AllocFailed` as soon as the total would exceed the
+/// limit, exactly as if the allocator had run out of memory.
+///
+/// Each growth is charged the number of bytes the vector's buffer grows
+/// by. Memory released by dropping vectors isn't observed and is never
+/// credited back, so `used` is an upper bound on what is live; create
+/// one budget per parse.
+///
+/// Only growth through the `Vec` methods of the budget itself is charged.
+/// Growing through `FallibleVec` directly, or through `FallibleString`,
+/// `TryClone`, `SortedVecMap` and the other collections, bypasses it.
+#[derive(Debug)]
+pub struct AllocBudget {
+    limit: usize,
+    used: Cell<usize>,
+}
+
+impl AllocBudget {
+    /// Creates a budget that allows at most |limit| bytes in total.
+    pub fn new(limit: usize) -> AllocBudget {
+        AllocBudget { limit, used: Cell::new(0) }
+    }
+
+    /// The total number of bytes this budget allows.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The number of bytes charged so far.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// The number of bytes that can still be charged.
+    pub fn remaining(&self) -> usize {
+        self.limit - self.used.get()
+    }
+
+    /// Charges |bytes| against the budget, for an allocation of |layout|.
+    /// Returns Err(_) with that layout, charging nothing, if the budget
+    /// doesn't have |bytes| remaining.
+    pub fn try_charge(&self, bytes: usize, layout: Layout) -> Result<(), TryReserveError> {
+        if bytes > self.remaining() {
+            return Err(TryReserveError::AllocFailed { layout });
+        }
+        self.used.set(self.used.get() + bytes);
+        Ok(())
+    }
+
+    /// Gives back |bytes| previously charged, e.g. after the allocation
+    /// they were charged for failed.
+    pub fn release(&self, bytes: usize) {
+        debug_assert!(bytes <= self.used.get());
+        self.used.set(self.used.get().saturating_sub(bytes));
+    }
+
+    /// `FallibleVec::try_with_capacity`, charged against this budget.
+    pub fn try_with_capacity<T>(&self, capacity: usize) -> Result<Vec<T>, TryReserveError> {
+        let mut vec = Vec::new();
+        try_extend_vec(&mut vec, capacity, Some(self))?;
+        Ok(vec)
+    }
+
+    /// `FallibleVec::try_push`, charged against this budget.
+    pub fn try_push<T>(&self, vec: &mut Vec<T>, value: T) -> Result<(), TryReserveError> {
+        vec_try_push(vec, value, Some(self))
+    }
+
+    /// `FallibleVec::try_reserve`, charged against this budget.
+    pub fn try_reserve<T>(&self, vec: &mut Vec<T>, additional: usize) -> Result<(), TryReserveError> {
+        vec_try_reserve(vec, additional, Some(self))
+    }
+
+    /// `FallibleVec::try_extend_from_slice`, charged against this budget.
+    pub fn try_extend_from_slice<T: Clone>(&self, vec: &mut Vec<T>, other: &[T]) -> Result<(), TryReserveError> {
+        vec_try_reserve(vec, other.len(), Some(self))?;
+        vec.extend_from_slice(other);
+        Ok(())
+    }
+
+    /// `FallibleVec::try_extend`, charged against this budget.
+    pub fn try_extend<T, I: IntoIterator<Item = T>>(&self, vec: &mut Vec<T>, iter: I) -> Result<(), TryReserveError> {
+        let iter = iter.into_iter();
+        vec_try_reserve(vec, iter.size_hint().0, Some(self))?;
+        for item in iter {
+            vec_try_push(vec, item, Some(self))?;
+        }
+        Ok(())
+    }
+
+    /// `FallibleVec::try_insert`, charged against this budget.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn try_insert<T>(&self, vec: &mut Vec<T>, index: usize, value: T) -> Result<(), TryReserveError> {
+        let len = vec.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        vec_try_reserve(vec, 1, Some(self))?;
+        vec.insert(index, value);
+        Ok(())
+    }
+
+    /// `FallibleVec::try_resize`, charged against this budget.
+    pub fn try_resize<T: Clone>(&self, vec: &mut Vec<T>, new_len: usize, value: T) -> Result<(), TryReserveError> {
+        if new_len > vec.len() {
+            vec_try_reserve(vec, new_len - vec.len(), Some(self))?;
+        }
+        vec.resize(new_len, value);
+        Ok(())
+    }
+
+    /// `FallibleVec::try_resize_with`, charged against this budget.
+    pub fn try_resize_with<T, F: FnMut() -> T>(&self, vec: &mut Vec<T>, new_len: usize, f: F)
+        -> Result<(), TryReserveError>
+    {
+        if new_len > vec.len() {
+            vec_try_reserve(vec, new_len - vec.len(), Some(self))?;
+        }
+        vec.resize_with(new_len, f);
+        Ok(())
+    }
+}
+
+#[test]
+fn charges_growth() {
+    let budget = AllocBudget::new(1024);
+    let mut vec: Vec<u32> = Vec::new();
+    budget.try_push(&mut vec, 1).unwrap();
+    assert_eq!(budget.used(), 4 * 4);
+    budget.try_extend_from_slice(&mut vec, &[2, 3, 4, 5]).unwrap();
+    assert_eq!(budget.used(), vec.capacity() * 4);
+    budget.try_reserve(&mut vec, 10).unwrap();
+    assert_eq!(budget.used(), vec.capacity() * 4);
+    assert_eq!(budget.remaining(), 1024 - budget.used());
+}
+
+#[test]
+fn exceeded() {
+    // A count that overcommit would let through.
+    let budget = AllocBudget::new(1 << 20);
+    match budget.try_with_capacity::<u32>(1 << 30) {
+        Err(TryReserveError::AllocFailed { layout }) => assert_eq!(layout.size(), 4 << 30),
+        r => panic!("budget should be exceeded, got {:?}", r.map(|v| v.capacity())),
+    }
+    assert_eq!(budget.used(), 0);
+
+    let mut vec = budget.try_with_capacity::<u8>(1 << 19).unwrap();
+    vec.resize(1 << 19, 0);
+    budget.try_reserve(&mut vec, 1 << 19).unwrap();
+    assert_eq!(budget.used(), 1 << 20);
+    vec.resize(1 << 20, 0);
+    assert!(budget.try_push(&mut vec, 0).is_err());
+    assert_eq!(budget.used(), 1 << 20);
+    assert_eq!(vec.len(), 1 << 20);
+}
+
+#[test]
+fn resize_and_extend() {
+    let budget = AllocBudget::new(4096);
+    let mut vec: Vec<u8> = Vec::new();
+    budget.try_resize(&mut vec, 1000, 0).unwrap();
+    budget.try_insert(&mut vec, 0, 1).unwrap();
+    budget.try_extend(&mut vec, (0..100).filter(|_| true)).unwrap();
+    assert_eq!(vec.len(), 1101);
+    assert_eq!(vec[0], 1);
+    assert_eq!(budget.used(), vec.capacity());
+
+    // A buffer resized to a length declared by the file.
+    let mut buf: Vec<u32> = Vec::new();
+    assert!(budget.try_resize_with(&mut buf, 0x1000_0000, || 0).is_err());
+    assert!(budget.try_extend(&mut buf, 0..0x1000_0000).is_err());
+    assert!(buf.is_empty());
+    assert_eq!(budget.used(), vec.capacity());
+}
+
+#[test]
+fn falls_back_to_exact_growth() {
+    let budget = AllocBudget::new(1000);
+    let mut vec: Vec<u8> = budget.try_with_capacity(600).unwrap();
+    vec.resize(600, 0);
+    // Doubling to 1200 bytes is over the budget, but 610 isn't.
+    budget.try_reserve(&mut vec, 10).unwrap();
+    assert_eq!(vec.capacity(), 610);
+    vec.resize(610, 0);
+    budget.try_extend_from_slice(&mut vec, &[0; 10]).unwrap();
+    assert_eq!(budget.used(), 620);
+}
+
+#[test]
+fn failed_allocation_is_not_charged() {
+    let budget = AllocBudget::new(usize::MAX);
+    let mut vec: Vec<u8> = Vec::new();
+    assert!(budget.try_reserve(&mut vec, usize::MAX / 4).is_err());
+    assert_eq!(budget.used(), 0);
+}
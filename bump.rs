@@ -0,0 +1,138 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::{self as heap, Layout};
+use core::cell::Cell;
+use core::ptr::{self, NonNull};
+
+use super::TryReserveError;
+use super::allocator::{AllocError, Allocator, dangling};
+use super::try_alloc;
+
+/// A bump allocator over a single fixed-size region.
+///
+/// The region is allocated fallibly once, up front. Allocations are
+/// carved off its end in order and fail with `AllocError` once it is
+/// exhausted. Individual frees only give memory back for the most recent
+/// allocation (which makes growing the newest `AllocVec` cheap); the
+/// whole region is freed when the arena is dropped.
+pub struct BumpArena {
+    start: NonNull<u8>,
+    capacity: usize,
+    used: Cell<usize>,
+}
+
+// The arena owns its region outright.
+unsafe impl Send for BumpArena {}
+
+// Alignment of the region itself; larger alignments are satisfied by
+// padding.
+const REGION_ALIGN: usize = 16;
+
+impl BumpArena {
+    /// Allocates a region of |capacity| bytes. Returns Err(_) on lack of
+    /// memory or if the size overflows.
+    pub fn try_with_capacity(capacity: usize) -> Result<BumpArena, TryReserveError> {
+        let layout = Layout::from_size_align(capacity, REGION_ALIGN)
+            .map_err(|_| TryReserveError::CapacityOverflow)?;
+        let start = if capacity == 0 {
+            dangling(layout)
+        } else {
+            NonNull::new(try_alloc(layout, false)?).expect("non-null")
+        };
+        Ok(BumpArena { start, capacity, used: Cell::new(0) })
+    }
+
+    /// The size of the region, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of bytes handed out so far, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Makes the whole region available again. Takes `&mut self`, so no
+    /// allocation from the arena can still be alive.
+    pub fn reset(&mut self) {
+        self.used.set(0);
+    }
+
+    /// The offset at which a block for |layout| would start, or None if it
+    /// doesn't fit.
+    fn fit(&self, layout: Layout) -> Option<usize> {
+        let base = self.start.as_ptr() as usize;
+        let unaligned = base.checked_add(self.used.get())?;
+        let aligned = unaligned.checked_add(layout.align() - 1)? & !(layout.align() - 1);
+        let offset = aligned - base;
+        if offset.checked_add(layout.size())? > self.capacity {
+            return None;
+        }
+        Some(offset)
+    }
+
+    /// Whether |ptr| points into this arena's region.
+    pub(crate) fn contains(&self, ptr: NonNull<u8>) -> bool {
+        let start = self.start.as_ptr() as usize;
+        let addr = ptr.as_ptr() as usize;
+        addr >= start && addr < start + self.capacity
+    }
+
+    fn is_last(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        ptr.as_ptr() as usize + layout.size() == self.start.as_ptr() as usize + self.used.get()
+    }
+}
+
+impl Drop for BumpArena {
+    fn drop(&mut self) {
+        if self.capacity != 0 {
+            unsafe {
+                let layout = Layout::from_size_align_unchecked(self.capacity, REGION_ALIGN);
+                heap::dealloc(self.start.as_ptr(), layout);
+            }
+        }
+    }
+}
+
+unsafe impl Allocator for BumpArena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let offset = self.fit(layout).ok_or(AllocError)?;
+        self.used.set(offset + layout.size());
+        let ptr = unsafe { NonNull::new_unchecked(self.start.as_ptr().add(offset)) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_last(ptr, layout) {
+            self.used.set(self.used.get() - layout.size());
+        }
+    }
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout)
+        -> Result<NonNull<[u8]>, AllocError>
+    {
+        let offset = ptr.as_ptr() as usize - self.start.as_ptr() as usize;
+        let aligned = ptr.as_ptr() as usize & (new_layout.align() - 1) == 0;
+        if self.is_last(ptr, old_layout) && aligned && new_layout.size() <= self.capacity - offset {
+            // Extend the newest block in place.
+            self.used.set(offset + new_layout.size());
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+        Ok(new_ptr)
+    }
+}
+
+#[test]
+fn contains_excludes_end() {
+    let arena = BumpArena::try_with_capacity(64).unwrap();
+    let start = arena.start.as_ptr() as usize;
+    let at = |addr: usize| NonNull::new(addr as *mut u8).unwrap();
+    assert!(arena.contains(at(start)));
+    assert!(arena.contains(at(start + 63)));
+    // One past the end may be the start of a neighbouring chunk.
+    assert!(!arena.contains(at(start + 64)));
+}
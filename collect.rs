@@ -0,0 +1,225 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Fallible collecting.
+//!
+//! `Iterator::collect` aborts if an allocation fails. `TryFromIterator`
+//! is the fallible equivalent of `FromIterator`, and the `TryCollect`
+//! extension trait calls it from an iterator chain. Items that are
+//! themselves `Result`s are collected with `try_collect_results`, which
+//! stops at the first error and folds allocation failure into the same
+//! error type:
+//!
+//! ```
+//! use mp4parse_fallible::{TryCollect, TryReserveError};
+//!
+//! #[derive(Debug)]
+//! enum Error {
+//!     InvalidData,
+//!     OutOfMemory,
+//! }
+//!
+//! impl From<TryReserveError> for Error {
+//!     fn from(_: TryReserveError) -> Self {
+//!         Error::OutOfMemory
+//!     }
+//! }
+//!
+//! fn parse_entry(raw: &[u8]) -> Result<u16, Error> {
+//!     match *raw {
+//!         [hi, lo] => Ok(u16::from_be_bytes([hi, lo])),
+//!         _ => Err(Error::InvalidData),
+//!     }
+//! }
+//!
+//! let data = [0, 1, 0, 2, 0, 3];
+//! let entries: Vec<u16> = data.chunks(2).map(parse_entry).try_collect_results().unwrap();
+//! assert_eq!(entries, [1, 2, 3]);
+//! ```
+//!
+//! The methods aren't called `try_collect` because that name is taken by
+//! an unstable `Iterator` method.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{FallibleString, FallibleVec, TryReserveError};
+
+/// Fallible counterpart of `FromIterator`.
+pub trait TryFromIterator<A>: Sized {
+    /// Creates a value from the items of |iter|. The iterator's lower
+    /// `size_hint` is reserved up front. Returns Err(_) if it fails,
+    /// which can only be due to lack of memory.
+    fn try_from_iter<I: IntoIterator<Item = A>>(iter: I) -> Result<Self, TryReserveError>;
+}
+
+impl<T> TryFromIterator<T> for Vec<T> {
+    fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, TryReserveError> {
+        let mut vec = Vec::new();
+        vec.try_extend(iter)?;
+        Ok(vec)
+    }
+}
+
+impl<T> TryFromIterator<T> for Box<[T]> {
+    fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, TryReserveError> {
+        Vec::try_from_iter(iter)?.try_into_boxed_slice()
+    }
+}
+
+impl TryFromIterator<char> for String {
+    fn try_from_iter<I: IntoIterator<Item = char>>(iter: I) -> Result<Self, TryReserveError> {
+        let iter = iter.into_iter();
+        let mut s = String::new();
+        // Each char takes at least one byte.
+        FallibleString::try_reserve(&mut s, iter.size_hint().0)?;
+        for ch in iter {
+            s.try_push(ch)?;
+        }
+        Ok(s)
+    }
+}
+
+impl<'a> TryFromIterator<&'a str> for String {
+    fn try_from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Result<Self, TryReserveError> {
+        let mut s = String::new();
+        for part in iter {
+            s.try_push_str(part)?;
+        }
+        Ok(s)
+    }
+}
+
+/// Yields the `Ok` values of |iter| until the first `Err`, which is
+/// stored in |error|.
+struct Shunt<'a, I, E> {
+    iter: I,
+    error: &'a mut Option<E>,
+}
+
+impl<I, T, E> Iterator for Shunt<'_, I, E> where I: Iterator<Item = Result<T, E>> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.iter.next()? {
+            Ok(item) => Some(item),
+            Err(e) => {
+                *self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.error.is_some() {
+            return (0, Some(0));
+        }
+        // Any item may be an error, so nothing is guaranteed. Reserving the
+        // inner lower bound would also trust a lying count before a single
+        // item is parsed.
+        (0, self.iter.size_hint().1)
+    }
+}
+
+/// Extension methods for collecting an iterator fallibly.
+pub trait TryCollect: Iterator + Sized {
+    /// Collects the items into |C|, like `collect`. Returns Err(_) if it
+    /// fails, which can only be due to lack of memory.
+    fn try_collect_into<C: TryFromIterator<Self::Item>>(self) -> Result<C, TryReserveError> {
+        C::try_from_iter(self)
+    }
+
+    /// Collects the `Ok` values of an iterator of `Result`s into |C|,
+    /// like `collect::<Result<C, E>>`. Returns the first `Err` item, or
+    /// the allocation failure converted into |E|. Nothing is reserved up
+    /// front, since any item may be an error; |C| grows as items arrive.
+    fn try_collect_results<C, T, E>(self) -> Result<C, E>
+        where Self: Iterator<Item = Result<T, E>>,
+              C: TryFromIterator<T>,
+              E: From<TryReserveError>
+    {
+        let mut error = None;
+        let collected = C::try_from_iter(Shunt { iter: self, error: &mut error });
+        match error {
+            Some(e) => Err(e),
+            None => collected.map_err(E::from),
+        }
+    }
+}
+
+impl<I: Iterator> TryCollect for I {}
+
+#[test]
+fn collect_containers() {
+    let vec: Vec<u32> = (1..4).try_collect_into().unwrap();
+    assert_eq!(vec, [1, 2, 3]);
+    assert_eq!(vec.capacity(), 3);
+
+    let boxed: Box<[u32]> = (1..4).map(|n| n * 2).try_collect_into().unwrap();
+    assert_eq!(&*boxed, [2, 4, 6]);
+
+    let s: String = "moov".chars().rev().try_collect_into().unwrap();
+    assert_eq!(s, "voom");
+    let s: String = ["ft", "yp"].iter().cloned().try_collect_into().unwrap();
+    assert_eq!(s, "ftyp");
+}
+
+#[test]
+fn collect_results() {
+    #[derive(Debug, PartialEq)]
+    enum Error {
+        Parse(usize),
+        Alloc(TryReserveError),
+    }
+
+    impl From<TryReserveError> for Error {
+        fn from(e: TryReserveError) -> Self {
+            Error::Alloc(e)
+        }
+    }
+
+    let ok: Result<Vec<usize>, Error> = (0..5).map(Ok).try_collect_results();
+    assert_eq!(ok.unwrap(), [0, 1, 2, 3, 4]);
+
+    let mut consumed = 0;
+    let failed: Result<Box<[usize]>, Error> = (0..5)
+        .inspect(|_| consumed += 1)
+        .map(|n| if n == 2 { Err(Error::Parse(n)) } else { Ok(n) })
+        .try_collect_results();
+    assert_eq!(failed.unwrap_err(), Error::Parse(2));
+    assert_eq!(consumed, 3);
+
+    // A declared count of 2^40 entries whose first entry is invalid.
+    let lying: Result<Vec<u64>, Error> = (0..1u64 << 40)
+        .map(|n| if n == 0 { Err(Error::Parse(0)) } else { Ok(n) })
+        .try_collect_results();
+    assert_eq!(lying.unwrap_err(), Error::Parse(0));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn collect_results_oom() {
+    #[derive(Debug, PartialEq)]
+    enum Error {
+        Alloc(TryReserveError),
+    }
+
+    impl From<TryReserveError> for Error {
+        fn from(e: TryReserveError) -> Self {
+            Error::Alloc(e)
+        }
+    }
+
+    let (oom, _) = super::with_alloc_limit(1024, || {
+        (0..1000u64).map(Ok).try_collect_results::<Vec<u64>, _, Error>()
+    });
+    match oom {
+        Err(Error::Alloc(TryReserveError::AllocFailed { .. })) => (),
+        r => panic!("it should be OOM, got {:?}", r.map(|v| v.len())),
+    }
+}
@@ -0,0 +1,155 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use core::mem;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::{self, HashSet};
+use core::hash::{BuildHasher, Hash};
+
+use super::{TryReserveError, try_grow_with};
+
+/// Fallible counterparts of the growing `HashMap` methods.
+///
+/// `HashMap` has inherent `try_reserve` and (unstable) `try_insert`
+/// methods that take precedence over these, so call them as
+/// `FallibleHashMap::try_insert(&mut map, k, v)`.
+///
+/// Growth is only approximately accounted for; see `with_alloc_limit`.
+pub trait FallibleHashMap<K, V> {
+    /// Inserts a key-value pair like `HashMap::insert`, returning the old
+    /// value if |key| was present. Returns Err(_) if growing the table
+    /// fails, in which case the map is unchanged.
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>;
+
+    /// Reserves capacity for at least `additional` more elements. Returns
+    /// Err(_) on lack of memory or if the capacity overflows.
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+
+    /// Gets the entry for |key| like `HashMap::entry`, after making room
+    /// for one more element so that inserting through the entry can't
+    /// abort. Returns Err(_) if growing the table fails.
+    fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V>, TryReserveError>;
+}
+
+/// Fallible counterparts of the growing `HashSet` methods. As with
+/// `FallibleHashMap`, call `try_reserve` as
+/// `FallibleHashSet::try_reserve(&mut set, n)`.
+pub trait FallibleHashSet<T> {
+    /// Adds |value| to the set like `HashSet::insert`, returning whether
+    /// it was newly inserted. Returns Err(_) if growing the table fails.
+    fn try_insert(&mut self, value: T) -> Result<bool, TryReserveError>;
+
+    /// Reserves capacity for at least `additional` more elements. Returns
+    /// Err(_) on lack of memory or if the capacity overflows.
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+}
+
+/// Makes room for |additional| more elements of type |T| in a table
+/// holding |len| of |capacity|, using |reserve|, std's `try_reserve`.
+///
+/// std doesn't say what it asks the allocator for, so the allocation
+/// hooks are consulted with, and failures report, the layout of an array
+/// of |len + additional| elements. That is a lower bound on the size of
+/// the table, which also has empty buckets and control bytes.
+fn reserve_table<T, F>(len: usize, capacity: usize, additional: usize, reserve: F)
+    -> Result<(), TryReserveError>
+    where F: FnOnce() -> Result<(), collections::TryReserveError>
+{
+    if capacity - len >= additional {
+        return Ok(());
+    }
+    let layout = len.checked_add(additional)
+        .and_then(|needed| Layout::array::<T>(needed).ok())
+        .ok_or(TryReserveError::CapacityOverflow)?;
+    let growth = layout.size().saturating_sub(capacity.saturating_mul(mem::size_of::<T>()));
+    try_grow_with(growth, layout, || {
+        reserve().map_err(|_| TryReserveError::AllocFailed { layout })
+    })
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> FallibleHashMap<K, V> for HashMap<K, V, S> {
+    #[inline]
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError> {
+        FallibleHashMap::try_reserve(self, 1)?;
+        Ok(self.insert(key, value))
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let (len, capacity) = (self.len(), self.capacity());
+        reserve_table::<(K, V), _>(len, capacity, additional, || HashMap::try_reserve(self, additional))
+    }
+
+    #[inline]
+    fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V>, TryReserveError> {
+        FallibleHashMap::try_reserve(self, 1)?;
+        Ok(self.entry(key))
+    }
+}
+
+impl<T: Eq + Hash, S: BuildHasher> FallibleHashSet<T> for HashSet<T, S> {
+    #[inline]
+    fn try_insert(&mut self, value: T) -> Result<bool, TryReserveError> {
+        FallibleHashSet::try_reserve(self, 1)?;
+        Ok(self.insert(value))
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let (len, capacity) = (self.len(), self.capacity());
+        reserve_table::<T, _>(len, capacity, additional, || HashSet::try_reserve(self, additional))
+    }
+}
+
+#[test]
+fn hash_map() {
+    let mut map = HashMap::new();
+    assert_eq!(FallibleHashMap::try_insert(&mut map, 1u32, "a"), Ok(None));
+    assert_eq!(FallibleHashMap::try_insert(&mut map, 1u32, "b"), Ok(Some("a")));
+    *FallibleHashMap::try_entry(&mut map, 2).unwrap().or_insert("c") = "d";
+    assert_eq!(map.len(), 2);
+    assert_eq!(map[&2], "d");
+
+    FallibleHashMap::try_reserve(&mut map, 100).unwrap();
+    assert!(map.capacity() >= 102);
+}
+
+#[test]
+fn hash_map_oom() {
+    let mut map: HashMap<u32, u64> = HashMap::new();
+    FallibleHashMap::try_insert(&mut map, 1, 1).unwrap();
+    assert_eq!(FallibleHashMap::try_reserve(&mut map, usize::MAX),
+               Err(TryReserveError::CapacityOverflow));
+    assert_eq!(FallibleHashMap::try_reserve(&mut map, usize::MAX / 4),
+               Err(TryReserveError::CapacityOverflow));
+    // More than the address space, but the array of entries fits isize.
+    match FallibleHashMap::try_reserve(&mut map, 1 << 44) {
+        Err(TryReserveError::AllocFailed { .. }) => (),
+        r => panic!("it should be OOM, got {:?}", r),
+    }
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn hash_map_limited() {
+    let mut map: HashMap<u32, u64> = HashMap::new();
+    let (result, peak) = super::with_alloc_limit(1024, || {
+        FallibleHashMap::try_reserve(&mut map, 10)?;
+        FallibleHashMap::try_reserve(&mut map, 1000)
+    });
+    assert!(result.is_err(), "a table of 1000 entries is over the limit");
+    assert!((10 * 16..=1024).contains(&peak));
+    assert!(map.capacity() < 1000);
+}
+
+#[test]
+fn hash_set() {
+    let mut set = HashSet::new();
+    assert_eq!(FallibleHashSet::try_insert(&mut set, 7u32), Ok(true));
+    assert_eq!(FallibleHashSet::try_insert(&mut set, 7u32), Ok(false));
+    assert_eq!(FallibleHashSet::try_reserve(&mut set, usize::MAX),
+               Err(TryReserveError::CapacityOverflow));
+    assert_eq!(set.len(), 1);
+}
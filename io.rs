@@ -0,0 +1,390 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Fallible replacements for `Read::read_to_end` and friends, and
+//! `io::Write` sinks that don't abort on OOM.
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::{FallibleVec, TryReserveError};
+
+/// The size of the stack buffer reads go through. The Vec only grows by
+/// what each read returns, so a lying size field can't force a large
+/// up-front allocation, and short reads don't pay for zeroing spare
+/// capacity.
+const READ_CHUNK: usize = 8 * 1024;
+
+/// The error returned by the fallible read helpers.
+#[derive(Debug)]
+pub enum TryReadError {
+    /// The reader failed, or ended before the requested length.
+    Io(io::Error),
+    /// Growing the buffer failed.
+    Alloc(TryReserveError),
+    /// The reader had more data than the caller's limit allows.
+    LimitExceeded,
+}
+
+impl fmt::Display for TryReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryReadError::Io(ref e) => write!(f, "read failed: {}", e),
+            TryReadError::Alloc(ref e) => write!(f, "read buffer allocation failed: {}", e),
+            TryReadError::LimitExceeded => f.write_str("read limit exceeded"),
+        }
+    }
+}
+
+impl Error for TryReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            TryReadError::Io(ref e) => Some(e),
+            TryReadError::Alloc(ref e) => Some(e),
+            TryReadError::LimitExceeded => None,
+        }
+    }
+}
+
+impl From<io::Error> for TryReadError {
+    fn from(e: io::Error) -> Self {
+        TryReadError::Io(e)
+    }
+}
+
+impl From<TryReserveError> for TryReadError {
+    fn from(e: TryReserveError) -> Self {
+        TryReadError::Alloc(e)
+    }
+}
+
+impl From<TryReadError> for io::Error {
+    fn from(e: TryReadError) -> Self {
+        match e {
+            TryReadError::Io(e) => e,
+            TryReadError::Alloc(e) => io::Error::new(io::ErrorKind::OutOfMemory, e),
+            TryReadError::LimitExceeded => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Reads up to |want| bytes from |reader| through |scratch| and appends
+/// them to |buf|, growing it fallibly first. Returns the number of bytes
+/// read, which is 0 at end of input.
+fn read_chunk<R: Read + ?Sized>(reader: &mut R, buf: &mut Vec<u8>, scratch: &mut [u8; READ_CHUNK], want: usize)
+    -> Result<usize, TryReadError>
+{
+    let scratch = &mut scratch[..cmp::min(want, READ_CHUNK)];
+    FallibleVec::try_reserve(buf, scratch.len())?;
+    loop {
+        match reader.read(scratch) {
+            Ok(n) => {
+                // Reserved above, so this doesn't allocate.
+                buf.extend_from_slice(&scratch[..n]);
+                return Ok(n);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Reads everything from |reader| into the end of |buf|, like
+/// `Read::read_to_end`, but growing |buf| fallibly, a bounded chunk at a
+/// time, and reading at most |limit| bytes. Returns the number of bytes
+/// read.
+///
+/// If the reader has more than |limit| bytes, fails with
+/// `TryReadError::LimitExceeded`. On any error the bytes read so far are
+/// left in |buf|. Telling that there is more data takes reading one more
+/// byte, which is discarded, so after `LimitExceeded` the reader is one
+/// byte past the end of |buf|; wrap it in `Read::take(limit)` instead if
+/// the rest must still be read.
+pub fn try_read_to_end<R: Read + ?Sized>(reader: &mut R, buf: &mut Vec<u8>, limit: usize)
+    -> Result<usize, TryReadError>
+{
+    let start = buf.len();
+    let mut scratch = [0; READ_CHUNK];
+    loop {
+        let read = buf.len() - start;
+        if read == limit {
+            // Check for more data without growing the buffer.
+            let mut probe = [0u8; 1];
+            loop {
+                match reader.read(&mut probe) {
+                    Ok(0) => return Ok(read),
+                    Ok(_) => return Err(TryReadError::LimitExceeded),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        if read_chunk(reader, buf, &mut scratch, limit - read)? == 0 {
+            return Ok(read);
+        }
+    }
+}
+
+/// Reads exactly |n| bytes from |reader| into a new Vec, like
+/// `Read::read_exact`. The buffer grows as data arrives, a bounded chunk
+/// at a time, so a bogus |n| from a size field fails with an I/O error
+/// once the input runs out instead of allocating |n| bytes up front.
+pub fn try_read_exact_vec<R: Read + ?Sized>(reader: &mut R, n: usize) -> Result<Vec<u8>, TryReadError> {
+    let mut buf = Vec::new();
+    let mut scratch = [0; READ_CHUNK];
+    while buf.len() < n {
+        let want = n - buf.len();
+        if read_chunk(reader, &mut buf, &mut scratch, want)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer").into());
+        }
+    }
+    Ok(buf)
+}
+
+fn out_of_memory(e: TryReserveError) -> io::Error {
+    io::Error::new(io::ErrorKind::OutOfMemory, e)
+}
+
+/// An `io::Write` sink appending to a `Vec<u8>`, like `impl Write for
+/// Vec<u8>`, but failing with `io::ErrorKind::OutOfMemory` instead of
+/// aborting when the vector can't grow.
+#[derive(Debug)]
+pub struct FallibleWriter<'a> {
+    vec: &'a mut Vec<u8>,
+}
+
+impl<'a> FallibleWriter<'a> {
+    /// Creates a writer appending to the end of |vec|.
+    pub fn new(vec: &'a mut Vec<u8>) -> Self {
+        FallibleWriter { vec }
+    }
+
+    /// Returns the bytes written so far, including anything |vec| held
+    /// beforehand.
+    pub fn get_ref(&self) -> &Vec<u8> {
+        self.vec
+    }
+}
+
+impl Write for FallibleWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.vec.try_extend_from_slice(buf).map_err(out_of_memory)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write(buf).map(|_| ())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A seekable `io::Write` sink over a `Vec<u8>`, like `io::Cursor`, but
+/// growing the vector fallibly. Writing overwrites existing bytes at the
+/// current position, so box sizes can be back-patched once the contents
+/// are written. Seeking past the end and writing zero-fills the gap.
+#[derive(Debug)]
+pub struct FallibleCursor<'a> {
+    vec: &'a mut Vec<u8>,
+    pos: u64,
+}
+
+impl<'a> FallibleCursor<'a> {
+    /// Creates a cursor over |vec|, positioned at the start.
+    pub fn new(vec: &'a mut Vec<u8>) -> Self {
+        FallibleCursor { vec, pos: 0 }
+    }
+
+    /// Returns the current position.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Moves to |pos|, which may be past the end.
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    /// Returns the underlying vector.
+    pub fn get_ref(&self) -> &Vec<u8> {
+        self.vec
+    }
+}
+
+impl Write for FallibleCursor<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let overflow = || out_of_memory(TryReserveError::CapacityOverflow);
+        let pos = usize::try_from(self.pos).map_err(|_| overflow())?;
+        let end = pos.checked_add(buf.len()).ok_or_else(overflow)?;
+        if end > self.vec.len() {
+            let additional = end - self.vec.len();
+            FallibleVec::try_reserve(self.vec, additional).map_err(out_of_memory)?;
+            // Reserved above, so neither of these allocates.
+            if pos > self.vec.len() {
+                self.vec.resize(pos, 0);
+            }
+        }
+        let overlap = cmp::min(self.vec.len() - pos, buf.len());
+        self.vec[pos..pos + overlap].copy_from_slice(&buf[..overlap]);
+        self.vec.extend_from_slice(&buf[overlap..]);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write(buf).map(|_| ())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for FallibleCursor<'_> {
+    fn seek(&mut self, style: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match style {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.vec.len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[test]
+fn read_to_end() {
+    let data: Vec<u8> = (0..200_000u32).map(|n| n as u8).collect();
+    let mut buf = b"ftyp".to_vec();
+    assert_eq!(try_read_to_end(&mut &data[..], &mut buf, data.len()).unwrap(), data.len());
+    assert_eq!(&buf[..4], b"ftyp");
+    assert_eq!(&buf[4..], &data[..]);
+
+    let mut buf = Vec::new();
+    match try_read_to_end(&mut &data[..], &mut buf, 1000) {
+        Err(TryReadError::LimitExceeded) => (),
+        r => panic!("expected LimitExceeded, got {:?}", r),
+    }
+    assert_eq!(buf, &data[..1000]);
+    assert!(buf.capacity() < 4096);
+}
+
+#[test]
+fn short_reads() {
+    /// Returns one byte per call, like a slow socket.
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = cmp::min(1, cmp::min(buf.len(), self.0.len()));
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let data: Vec<u8> = (0..200_000u32).map(|n| n as u8).collect();
+    let mut buf = Vec::new();
+    assert_eq!(try_read_to_end(&mut Trickle(&data), &mut buf, usize::MAX).unwrap(), data.len());
+    assert_eq!(buf, data);
+    assert_eq!(try_read_exact_vec(&mut Trickle(&data), 1000).unwrap(), &data[..1000]);
+}
+
+#[test]
+fn read_exact_vec() {
+    let data = [7u8; 100];
+    assert_eq!(try_read_exact_vec(&mut &data[..], 60).unwrap(), &data[..60]);
+    assert!(try_read_exact_vec(&mut &data[..], 0).unwrap().is_empty());
+
+    // A size field claiming 4 GB in a 100 byte input.
+    match try_read_exact_vec(&mut &data[..], 0xFFFF_FFFF) {
+        Err(TryReadError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => (),
+        r => panic!("expected UnexpectedEof, got {:?}", r.map(|v| v.len())),
+    }
+}
+
+#[test]
+fn errors_are_distinguished() {
+    struct Failing;
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk on fire"))
+        }
+    }
+
+    let mut buf = Vec::new();
+    match try_read_to_end(&mut Failing, &mut buf, 10) {
+        Err(TryReadError::Io(ref e)) => assert_eq!(e.to_string(), "disk on fire"),
+        r => panic!("expected an I/O error, got {:?}", r),
+    }
+
+    let e: io::Error = TryReadError::Alloc(TryReserveError::CapacityOverflow).into();
+    assert_eq!(e.kind(), io::ErrorKind::OutOfMemory);
+}
+
+#[test]
+fn writer() {
+    let mut vec = b"ftyp".to_vec();
+    let mut w = FallibleWriter::new(&mut vec);
+    write!(w, "isom{}", 512).unwrap();
+    w.write_all(&[0, 0, 2, 0]).unwrap();
+    assert_eq!(w.get_ref().len(), 15);
+    assert_eq!(vec, b"ftypisom512\0\0\x02\0");
+
+    let mut vec = Vec::new();
+    let (result, _) = super::with_alloc_limit(1024, || {
+        FallibleWriter::new(&mut vec).write_all(&[0; 4096])
+    });
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+    assert!(vec.is_empty());
+}
+
+#[test]
+fn cursor_back_patch() {
+    let mut vec = Vec::new();
+    let mut c = FallibleCursor::new(&mut vec);
+    c.write_all(&[0; 4]).unwrap();
+    c.write_all(b"moov").unwrap();
+    c.write_all(&[1, 2, 3, 4, 5]).unwrap();
+    let size = c.position() as u32;
+    c.seek(SeekFrom::Start(0)).unwrap();
+    c.write_all(&size.to_be_bytes()).unwrap();
+    assert_eq!(c.position(), 4);
+    assert_eq!(c.seek(SeekFrom::End(0)).unwrap(), 13);
+    c.write_all(&[6]).unwrap();
+
+    // Overwriting across the end extends.
+    c.seek(SeekFrom::Current(-1)).unwrap();
+    c.write_all(&[7, 8]).unwrap();
+    assert!(c.seek(SeekFrom::Current(-100)).is_err());
+    assert_eq!(c.position(), 15);
+    assert_eq!(vec, b"\0\0\0\x0dmoov\x01\x02\x03\x04\x05\x07\x08");
+}
+
+#[test]
+fn cursor_gap_is_zeroed() {
+    let mut vec = vec![9];
+    let mut c = FallibleCursor::new(&mut vec);
+    c.set_position(4);
+    c.write_all(&[1]).unwrap();
+    assert_eq!(vec, [9, 0, 0, 0, 1]);
+
+    let mut c = FallibleCursor::new(&mut vec);
+    c.set_position(u64::MAX);
+    assert_eq!(c.write(&[1]).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+    assert_eq!(vec.len(), 5);
+}
@@ -2,30 +2,176 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use std::mem;
-use std::vec::Vec;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-extern "C" {
-    fn realloc(ptr: *mut u8, bytes: usize) -> *mut u8;
-    fn malloc(bytes: usize) -> *mut u8;
+extern crate alloc;
+
+use alloc::alloc::{self as heap, Layout};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp;
+use core::fmt;
+use core::mem;
+
+mod alloc_vec;
+mod allocator;
+mod arena;
+mod array_vec;
+mod boxed;
+mod budget;
+mod bump;
+mod collect;
+#[cfg(feature = "std")]
+mod hash;
+#[cfg(feature = "std")]
+mod io;
+#[cfg(feature = "std")]
+mod limit;
+#[cfg(feature = "smallvec")]
+mod small_vec;
+mod sorted_vec_map;
+#[cfg(feature = "stats")]
+mod stats;
+mod string;
+#[cfg(feature = "testing")]
+mod testing;
+mod try_clone;
+mod vec_deque;
+pub use alloc_vec::AllocVec;
+pub use allocator::{AllocError, Allocator, Global};
+pub use arena::{ArenaVec, FallibleArena};
+pub use array_vec::ArrayVec;
+pub use boxed::{Zeroable, try_box_new, try_new_zeroed, try_new_zeroed_slice};
+pub use budget::AllocBudget;
+pub use bump::BumpArena;
+pub use collect::{TryCollect, TryFromIterator};
+#[cfg(feature = "std")]
+pub use hash::{FallibleHashMap, FallibleHashSet};
+#[cfg(feature = "std")]
+pub use io::{FallibleCursor, FallibleWriter, TryReadError, try_read_exact_vec, try_read_to_end};
+#[cfg(feature = "std")]
+pub use limit::with_alloc_limit;
+pub use sorted_vec_map::SortedVecMap;
+#[cfg(feature = "stats")]
+pub use stats::AllocStats;
+pub use string::FallibleString;
+#[cfg(feature = "testing")]
+pub use testing::{FaultPolicy, FaultReport, with_fault_injection};
+pub use try_clone::{TryClone, try_to_vec};
+pub use vec_deque::FallibleVecDeque;
+
+/// The error returned when a fallible allocation fails.
+///
+/// Callers that only care about success or failure and still return
+/// `Result<_, ()>` can keep using `?`, since this converts into `()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TryReserveError {
+    /// The requested capacity exceeded what can be represented, either
+    /// as an element count or as a size in bytes.
+    CapacityOverflow,
+
+    /// The allocator returned an error when asked for |layout|.
+    AllocFailed {
+        /// The layout of the allocation that failed.
+        layout: Layout,
+    },
+}
+
+impl fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryReserveError::CapacityOverflow => {
+                f.write_str("capacity overflow")
+            }
+            TryReserveError::AllocFailed { layout } => {
+                write!(f, "memory allocation of {} bytes (align {}) failed",
+                       layout.size(), layout.align())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TryReserveError {}
+
+impl From<TryReserveError> for () {
+    fn from(_: TryReserveError) {}
 }
 
 pub trait FallibleVec<T> {
+    /// Constructs a new, empty vector with room for at least `capacity`
+    /// elements, like `Vec::with_capacity`. Use this instead of
+    /// `Vec::with_capacity` whenever `capacity` comes from the file
+    /// being parsed. Returns Err(_) on lack of memory or if the capacity
+    /// overflows.
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> where Self: Sized;
+
     /// Append |val| to the end of |vec|.  Returns Ok(()) on success,
-    /// Err(()) if it fails, which can only be due to lack of memory.
-    fn try_push(&mut self, value: T) -> Result<(), ()>;
+    /// Err(_) on lack of memory or if the capacity overflows.
+    fn try_push(&mut self, value: T) -> Result<(), TryReserveError>;
 
     /// Reserves capacity for at least `additional` more elements to
-    /// be inserted in the vector. Does nothing if capacity is already
-    /// sufficient. Return Ok(()) on success, Err(()) if it fails either
-    /// due to lack of memory, or overflowing the `usize` used to store
-    /// the capacity.
-    fn try_reserve(&mut self, additional: usize) -> Result<(), ()>;
+    /// be inserted in the vector. Like `Vec::reserve`, this may reserve
+    /// more space to avoid frequent reallocations, so a sequence of small
+    /// reserves has amortized linear cost. Does nothing if capacity is
+    /// already sufficient. Return Ok(()) on success, Err(_) if it fails
+    /// either due to lack of memory, or overflowing the `usize` used to
+    /// store the capacity.
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+
+    /// Reserves capacity for exactly `additional` more elements to be
+    /// inserted in the vector, like `Vec::reserve_exact`. Prefer
+    /// `try_reserve` if more insertions are expected. Errors as for
+    /// `try_reserve`.
+    fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError>;
+
+    /// Reserves capacity for `additional` more elements, but for no more
+    /// than |max_trusted| of them. Use this when |additional| comes from
+    /// untrusted input, such as an entry count in a box header: the
+    /// up-front allocation is bounded, and the vector then grows as
+    /// elements are actually pushed, so memory use follows the data read
+    /// rather than the declared count. Errors as for `try_reserve`.
+    #[inline]
+    fn try_reserve_bounded(&mut self, additional: usize, max_trusted: usize) -> Result<(), TryReserveError> {
+        self.try_reserve(cmp::min(additional, max_trusted))
+    }
 
     /// Clones and appends all elements in a slice to the Vec.
-    /// Returns Ok(()) on success, Err(()) if it fails, which can
-    /// only be due to lack of memory.
-    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), ()> where T: Clone;
+    /// Returns Ok(()) on success, Err(_) on lack of memory or if the
+    /// capacity overflows.
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> where T: Clone;
+
+    /// Appends all items produced by |iter|. The iterator's lower
+    /// `size_hint` is reserved up front, then the vector grows as needed.
+    /// Returns Ok(()) on success, Err(_) on lack of memory; on failure
+    /// the items appended so far are kept, the item that didn't fit is
+    /// dropped and the rest of the iterator is not consumed.
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError>;
+
+    /// Inserts |value| at position |index|, shifting all elements after
+    /// it to the right, like `Vec::insert`. Returns Err(_) on lack of
+    /// memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    fn try_insert(&mut self, index: usize, value: T) -> Result<(), TryReserveError>;
+
+    /// Resizes the vector in-place so that `len` is equal to |new_len|,
+    /// filling any new slots with clones of |value|, like `Vec::resize`.
+    /// Returns Err(_) on lack of memory, leaving the vector unchanged.
+    fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError> where T: Clone;
+
+    /// Resizes the vector in-place so that `len` is equal to |new_len|,
+    /// filling any new slots with values returned by |f|, like
+    /// `Vec::resize_with`. Returns Err(_) on lack of memory, leaving the
+    /// vector unchanged.
+    fn try_resize_with<F: FnMut() -> T>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>;
+
+    /// Converts the vector into `Box<[T]>`, shrinking the allocation to
+    /// fit like `Vec::into_boxed_slice`. Returns Err(_) if the shrinking
+    /// reallocation fails, which can only be due to lack of memory.
+    fn try_into_boxed_slice(self) -> Result<Box<[T]>, TryReserveError> where Self: Sized;
 }
 
 /////////////////////////////////////////////////////////////////
@@ -33,68 +179,296 @@ pub trait FallibleVec<T> {
 
 impl<T> FallibleVec<T> for Vec<T> {
     #[inline]
-    fn try_push(&mut self, val: T) -> Result<(), ()> {
-        if self.capacity() == self.len() {
-            let old_cap: usize = self.capacity();
-            let new_cap: usize
-                = if old_cap == 0 { 4 } else { old_cap.checked_mul(2).ok_or(()) ? };
-
-            try_extend_vec(self, new_cap)?;
-            debug_assert!(self.capacity() > self.len());
-        }
-        self.push(val);
-        Ok(())
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut vec = Vec::new();
+        try_extend_vec(&mut vec, capacity, None)?;
+        Ok(vec)
+    }
+
+    #[inline]
+    fn try_push(&mut self, val: T) -> Result<(), TryReserveError> {
+        vec_try_push(self, val, None)
     }
 
     #[inline]
-    fn try_reserve(&mut self, additional: usize) -> Result<(), ()> {
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        vec_try_reserve(self, additional, None)
+    }
+
+    #[inline]
+    fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
         let available = self.capacity().checked_sub(self.len()).expect("capacity >= len");
         if additional > available {
             let increase = additional.checked_sub(available).expect("additional > available");
-            let new_cap = self.capacity().checked_add(increase).ok_or(())?;
-            try_extend_vec(self, new_cap)?;
+            let new_cap = self.capacity().checked_add(increase)
+                .ok_or(TryReserveError::CapacityOverflow)?;
+            try_extend_vec(self, new_cap, None)?;
             debug_assert!(self.capacity() == new_cap);
         }
         Ok(())
     }
 
     #[inline]
-    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), ()> where T: Clone {
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> where T: Clone {
         FallibleVec::try_reserve(self, other.len())?;
         self.extend_from_slice(other);
         Ok(())
     }
+
+    #[inline]
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        let iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        FallibleVec::try_reserve(self, lower)?;
+        for item in iter {
+            FallibleVec::try_push(self, item)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_insert(&mut self, index: usize, value: T) -> Result<(), TryReserveError> {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        FallibleVec::try_reserve(self, 1)?;
+        self.insert(index, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError> where T: Clone {
+        if new_len > self.len() {
+            FallibleVec::try_reserve(self, new_len - self.len())?;
+        }
+        self.resize(new_len, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_resize_with<F: FnMut() -> T>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError> {
+        if new_len > self.len() {
+            FallibleVec::try_reserve(self, new_len - self.len())?;
+        }
+        self.resize_with(new_len, f);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_into_boxed_slice(mut self) -> Result<Box<[T]>, TryReserveError> {
+        try_shrink_vec(&mut self)?;
+        // Capacity now equals length, so this doesn't reallocate.
+        Ok(self.into_boxed_slice())
+    }
+}
+
+#[inline]
+fn vec_try_push<T>(vec: &mut Vec<T>, val: T, budget: Option<&AllocBudget>) -> Result<(), TryReserveError> {
+    if vec.capacity() == vec.len() {
+        let old_cap: usize = vec.capacity();
+        let new_cap: usize
+            = if old_cap == 0 { 4 } else {
+                old_cap.checked_mul(2).ok_or(TryReserveError::CapacityOverflow) ?
+            };
+
+        try_extend_vec(vec, new_cap, budget)?;
+        debug_assert!(vec.capacity() > vec.len());
+    }
+    vec.push(val);
+    Ok(())
+}
+
+#[inline]
+fn vec_try_reserve<T>(vec: &mut Vec<T>, additional: usize, budget: Option<&AllocBudget>)
+    -> Result<(), TryReserveError>
+{
+    let available = vec.capacity().checked_sub(vec.len()).expect("capacity >= len");
+    if additional > available {
+        let required = vec.len().checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        let new_cap = cmp::max(required, vec.capacity().saturating_mul(2));
+        match try_extend_vec(vec, new_cap, budget) {
+            // Doubling may overflow, or exceed a budget or limit, even
+            // when the requested capacity doesn't.
+            Err(_) if new_cap > required => {
+                try_extend_vec(vec, required, budget)?;
+            }
+            result => result?,
+        }
+        debug_assert!(vec.capacity() >= required);
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////
+// Allocation
+//
+// Everything the crate allocates goes through these, so the memory
+// always comes from the global allocator (whatever the application
+// installed with #[global_allocator]) and can be freed by std.
+
+/// Consulted before asking the allocator for |layout|, of which |bytes|
+/// are new memory.
+fn check_alloc(bytes: usize, layout: Layout) -> Result<(), TryReserveError> {
+    #[cfg(feature = "testing")]
+    testing::check(layout)?;
+    #[cfg(feature = "std")]
+    limit::try_charge(bytes, layout)?;
+    #[cfg(not(feature = "std"))]
+    let _ = (bytes, layout);
+    Ok(())
+}
+
+/// Called when |bytes| checked by `check_alloc` turn out not to be used.
+/// Freeing or shrinking memory isn't reported: it may have been allocated
+/// outside the current limit scope, which would then be credited with
+/// bytes it never charged.
+fn release_alloc(bytes: usize) {
+    #[cfg(feature = "std")]
+    limit::release(bytes);
+    #[cfg(not(feature = "std"))]
+    let _ = bytes;
+}
+
+/// Allocates |layout|, which must have a non-zero size.
+pub(crate) fn try_alloc(layout: Layout, zeroed: bool) -> Result<*mut u8, TryReserveError> {
+    debug_assert!(layout.size() != 0);
+    let result = check_alloc(layout.size(), layout).and_then(|()| {
+        let ptr = unsafe {
+            if zeroed {
+                heap::alloc_zeroed(layout)
+            } else {
+                heap::alloc(layout)
+            }
+        };
+        if ptr.is_null() {
+            release_alloc(layout.size());
+            return Err(TryReserveError::AllocFailed { layout });
+        }
+        Ok(ptr)
+    });
+    #[cfg(feature = "stats")]
+    stats::record(layout.size(), layout.size(), result.is_ok());
+    result
+}
+
+/// Resizes the block at |ptr|, currently allocated with |old_layout|, to
+/// |new_layout|, which must have the same alignment and a non-zero size.
+/// On failure the old block is left untouched.
+pub(crate) unsafe fn try_realloc(ptr: *mut u8, old_layout: Layout, new_layout: Layout)
+    -> Result<*mut u8, TryReserveError>
+{
+    debug_assert!(old_layout.align() == new_layout.align() && new_layout.size() != 0);
+    let growth = new_layout.size().saturating_sub(old_layout.size());
+    let check = if growth > 0 { check_alloc(growth, new_layout) } else { Ok(()) };
+    let result = check.and_then(|()| {
+        let new_ptr = heap::realloc(ptr, old_layout, new_layout.size());
+        if new_ptr.is_null() {
+            release_alloc(growth);
+            return Err(TryReserveError::AllocFailed { layout: new_layout });
+        }
+        Ok(new_ptr)
+    });
+    // Only growth counts; shrinking never requests new memory.
+    #[cfg(feature = "stats")]
+    {
+        if growth > 0 {
+            stats::record(new_layout.size(), growth, result.is_ok());
+        }
+    }
+    result
+}
+
+/// Grows a collection that allocates through std or another crate rather
+/// than through `try_alloc`. Consults the same hooks for |bytes| of new
+/// memory in |layout|, then runs |grow|, which does the allocation.
+pub(crate) fn try_grow_with<F>(bytes: usize, layout: Layout, grow: F) -> Result<(), TryReserveError>
+    where F: FnOnce() -> Result<(), TryReserveError>
+{
+    let result = check_alloc(bytes, layout).and_then(|()| {
+        grow().inspect_err(|_| release_alloc(bytes))
+    });
+    #[cfg(feature = "stats")]
+    stats::record(layout.size(), bytes, result.is_ok());
+    result
 }
 
 #[inline(never)]
 #[cold]
-fn try_extend_vec<T>(vec: &mut Vec<T>, new_cap: usize) -> Result<(), ()> {
+fn try_extend_vec<T>(vec: &mut Vec<T>, new_cap: usize, budget: Option<&AllocBudget>)
+    -> Result<(), TryReserveError>
+{
     let old_ptr = vec.as_mut_ptr();
     let old_len = vec.len();
 
     let old_cap: usize = vec.capacity();
 
-    if old_cap >= new_cap {
+    // Zero-sized types never allocate; their Vec already reports a
+    // capacity of usize::MAX.
+    if old_cap >= new_cap || mem::size_of::<T>() == 0 {
         return Ok(());
     }
 
-    let new_size_bytes
-        = new_cap.checked_mul(mem::size_of::<T>()).ok_or(()) ? ;
+    let new_layout = Layout::array::<T>(new_cap)
+        .map_err(|_| TryReserveError::CapacityOverflow) ? ;
 
-    let new_ptr = unsafe {
-        if old_cap == 0 {
-            malloc(new_size_bytes)
-        } else {
-            realloc(old_ptr as *mut u8, new_size_bytes)
+    let old_size = old_cap * mem::size_of::<T>();
+    let growth = new_layout.size() - old_size;
+    let charged = budget.map_or(Ok(()), |budget| budget.try_charge(growth, new_layout));
+    #[cfg(feature = "stats")]
+    {
+        if charged.is_err() {
+            stats::record(new_layout.size(), growth, false);
+        }
+    }
+    charged?;
+
+    // The buffer must have the layout Vec expects, or Vec::from_raw_parts
+    // (and the eventual dealloc in Vec's Drop) would be unsound.
+    let result = if old_cap == 0 {
+        try_alloc(new_layout, false)
+    } else {
+        let old_layout = Layout::array::<T>(old_cap).expect("existing Vec layout is valid");
+        unsafe { try_realloc(old_ptr as *mut u8, old_layout, new_layout) }
+    };
+    let new_ptr = match result {
+        Ok(ptr) => ptr,
+        Err(e) => {
+            if let Some(budget) = budget {
+                budget.release(growth);
+            }
+            return Err(e);
         }
     };
 
-    if new_ptr.is_null() {
-        return Err(());
+    let new_vec = unsafe {
+        Vec::from_raw_parts(new_ptr as *mut T, old_len, new_cap)
+    };
+
+    mem::forget(mem::replace(vec, new_vec));
+    Ok(())
+}
+
+/// Shrinks the capacity of |vec| to its length.
+fn try_shrink_vec<T>(vec: &mut Vec<T>) -> Result<(), TryReserveError> {
+    let old_cap = vec.capacity();
+    let len = vec.len();
+
+    if old_cap == len || mem::size_of::<T>() == 0 {
+        return Ok(());
     }
 
+    if len == 0 {
+        // Freeing never fails.
+        *vec = Vec::new();
+        return Ok(());
+    }
+
+    let old_layout = Layout::array::<T>(old_cap).expect("existing Vec layout is valid");
+    let new_layout = Layout::array::<T>(len).expect("smaller than an existing layout");
+    let new_ptr = unsafe { try_realloc(vec.as_mut_ptr() as *mut u8, old_layout, new_layout)? };
+
     let new_vec = unsafe {
-        Vec::from_raw_parts(new_ptr as *mut T, old_len, new_cap)
+        Vec::from_raw_parts(new_ptr as *mut T, len, len)
     };
 
     mem::forget(mem::replace(vec, new_vec));
@@ -102,6 +476,7 @@ fn try_extend_vec<T>(vec: &mut Vec<T>, new_cap: usize) -> Result<(), ()> {
 }
 
 #[test]
+#[allow(clippy::single_match, clippy::legacy_numeric_constants)]
 fn oom() {
     let mut vec: Vec<char> = Vec::new();
     match FallibleVec::try_reserve(&mut vec, std::usize::MAX) {
@@ -131,6 +506,135 @@ fn try_reserve_idempotent() {
 }
 
 #[test]
+fn try_reserve_amortized() {
+    let mut vec: Vec<u32> = Vec::new();
+    let mut reallocations = 0;
+    for i in 0..100_000 {
+        let old_cap = vec.capacity();
+        FallibleVec::try_reserve(&mut vec, 1).unwrap();
+        if vec.capacity() != old_cap {
+            reallocations += 1;
+        }
+        vec.push(i);
+    }
+    assert!(reallocations <= 20, "{} reallocations", reallocations);
+}
+
+#[test]
+fn try_reserve_exact() {
+    let mut vec: Vec<u32> = Vec::new();
+    for i in 0..10 {
+        FallibleVec::try_reserve_exact(&mut vec, 1).unwrap();
+        assert_eq!(vec.capacity(), i + 1);
+        vec.push(i as u32);
+    }
+    assert!(FallibleVec::try_reserve_exact(&mut vec, usize::MAX).is_err());
+}
+
+#[test]
+fn try_reserve_bounded() {
+    // A header claiming 0xFFFFFFFF entries when only a few follow.
+    let declared = 0xFFFF_FFFF;
+    let mut vec: Vec<u32> = Vec::new();
+    vec.try_reserve_bounded(declared, 1024).unwrap();
+    assert!(vec.capacity() >= 1024 && vec.capacity() < 2048);
+    for i in 0..3000 {
+        vec.try_push(i).unwrap();
+    }
+    assert!(vec.capacity() < 8192);
+
+    let mut vec: Vec<u32> = Vec::new();
+    vec.try_reserve_bounded(10, 1024).unwrap();
+    assert!(vec.capacity() >= 10 && vec.capacity() < 1024);
+}
+
+#[test]
+fn try_with_capacity() {
+    let vec: Vec<u32> = FallibleVec::try_with_capacity(100).unwrap();
+    assert!(vec.is_empty());
+    assert!(vec.capacity() >= 100);
+
+    let vec: Vec<u32> = FallibleVec::try_with_capacity(0).unwrap();
+    assert_eq!(vec.capacity(), 0);
+
+    let vec: Result<Vec<u32>, _> = FallibleVec::try_with_capacity(usize::MAX);
+    assert_eq!(vec, Err(TryReserveError::CapacityOverflow));
+
+    // Doesn't overflow, but no allocator can satisfy it.
+    let vec: Result<Vec<u8>, _> = FallibleVec::try_with_capacity(usize::MAX / 4);
+    match vec {
+        Err(TryReserveError::AllocFailed { .. }) => (),
+        r => panic!("it should be OOM, got {:?}", r.map(|v| v.capacity())),
+    }
+}
+
+#[test]
+fn try_extend() {
+    let mut vec = vec![1u32, 2];
+    FallibleVec::try_extend(&mut vec, 3..6).unwrap();
+    assert_eq!(vec, [1, 2, 3, 4, 5]);
+    assert!(vec.capacity() >= 5);
+
+    // No useful size_hint: grows incrementally.
+    FallibleVec::try_extend(&mut vec, (6..100).filter(|n| n % 2 == 0)).unwrap();
+    assert_eq!(vec.len(), 52);
+    assert_eq!(vec[51], 98);
+}
+
+#[test]
+fn try_extend_oom_keeps_contents() {
+    struct Claims(u32);
+    impl Iterator for Claims {
+        type Item = u32;
+        fn next(&mut self) -> Option<u32> {
+            self.0 += 1;
+            Some(self.0)
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (usize::MAX, None)
+        }
+    }
+
+    let mut vec = vec![1u32, 2, 3];
+    assert!(FallibleVec::try_extend(&mut vec, Claims(0)).is_err());
+    assert_eq!(vec, [1, 2, 3]);
+}
+
+#[test]
+fn try_insert() {
+    let mut vec = vec![1, 3];
+    FallibleVec::try_insert(&mut vec, 1, 2).unwrap();
+    FallibleVec::try_insert(&mut vec, 3, 4).unwrap();
+    FallibleVec::try_insert(&mut vec, 0, 0).unwrap();
+    assert_eq!(vec, [0, 1, 2, 3, 4]);
+}
+
+#[test]
+#[should_panic(expected = "insertion index (is 3) should be <= len (is 2)")]
+fn try_insert_out_of_bounds() {
+    let mut vec = vec![1, 2];
+    let _ = FallibleVec::try_insert(&mut vec, 3, 3);
+}
+
+#[test]
+fn try_resize() {
+    let mut vec = vec![1u8];
+    FallibleVec::try_resize(&mut vec, 4, 7).unwrap();
+    assert_eq!(vec, [1, 7, 7, 7]);
+    FallibleVec::try_resize(&mut vec, 2, 0).unwrap();
+    assert_eq!(vec, [1, 7]);
+
+    let mut next = 10;
+    FallibleVec::try_resize_with(&mut vec, 4, || { next += 1; next }).unwrap();
+    assert_eq!(vec, [1, 7, 11, 12]);
+
+    assert!(FallibleVec::try_resize(&mut vec, usize::MAX / 4, 0).is_err());
+    assert!(FallibleVec::try_resize_with(&mut vec, usize::MAX, || 0).is_err());
+    assert_eq!(vec, [1, 7, 11, 12]);
+}
+
+#[test]
+#[allow(clippy::single_match, clippy::legacy_numeric_constants)]
 fn capacity_overflow() {
     let mut vec = vec![1];
     match FallibleVec::try_reserve(&mut vec, std::usize::MAX) {
@@ -145,3 +649,76 @@ fn extend_from_slice() {
     FallibleVec::try_extend_from_slice(&mut vec, b"bar").unwrap();
     assert_eq!(&vec, b"foobar");
 }
+
+#[test]
+fn error_kinds() {
+    let mut vec: Vec<u32> = Vec::new();
+    assert_eq!(FallibleVec::try_reserve(&mut vec, usize::MAX),
+               Err(TryReserveError::CapacityOverflow));
+
+    let mut vec: Vec<u8> = Vec::new();
+    match FallibleVec::try_reserve(&mut vec, usize::MAX / 4) {
+        Err(TryReserveError::AllocFailed { layout }) => {
+            assert_eq!(layout.size(), usize::MAX / 4);
+            assert_eq!(layout.align(), 1);
+        }
+        r => panic!("it should be OOM, got {:?}", r),
+    }
+}
+
+#[test]
+fn unit_error_migration() {
+    fn reserve_huge(vec: &mut Vec<u32>) -> Result<(), ()> {
+        FallibleVec::try_reserve(vec, usize::MAX)?;
+        Ok(())
+    }
+    assert_eq!(reserve_huge(&mut Vec::new()), Err(()));
+    assert_eq!(TryReserveError::CapacityOverflow.to_string(), "capacity overflow");
+}
+
+#[test]
+fn zero_sized_type() {
+    let mut vec: Vec<()> = Vec::new();
+    for _ in 0..100 {
+        FallibleVec::try_push(&mut vec, ()).unwrap();
+    }
+    FallibleVec::try_reserve(&mut vec, 1000).unwrap();
+    FallibleVec::try_extend_from_slice(&mut vec, &[(); 10]).unwrap();
+    assert_eq!(vec.len(), 110);
+    assert_eq!(vec.capacity(), usize::MAX);
+    assert_eq!(FallibleVec::try_reserve(&mut vec, usize::MAX),
+               Err(TryReserveError::CapacityOverflow));
+}
+
+#[test]
+fn over_aligned_type() {
+    #[repr(align(64))]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Aligned(u8);
+
+    let mut vec: Vec<Aligned> = Vec::new();
+    for i in 0..100 {
+        FallibleVec::try_push(&mut vec, Aligned(i)).unwrap();
+        assert_eq!(vec.as_ptr() as usize % 64, 0);
+    }
+    FallibleVec::try_reserve(&mut vec, 1000).unwrap();
+    assert_eq!(vec.as_ptr() as usize % 64, 0);
+    for (i, a) in vec.iter().enumerate() {
+        assert_eq!(a.0 as usize, i);
+    }
+    // Vec's Drop must be able to free what we allocated.
+    drop(vec);
+}
+
+#[test]
+fn grow_from_std_allocation() {
+    let mut vec: Vec<u64> = Vec::with_capacity(3);
+    vec.extend_from_slice(&[1, 2, 3]);
+    FallibleVec::try_push(&mut vec, 4).unwrap();
+    FallibleVec::try_extend_from_slice(&mut vec, &[5, 6, 7, 8, 9]).unwrap();
+    assert_eq!(vec, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    // Growing with std after our reallocation must also work.
+    vec.reserve(100);
+    vec.push(10);
+    assert_eq!(vec.len(), 10);
+}
@@ -0,0 +1,173 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A per-thread allocation limit, for callers that can't thread an
+//! `AllocBudget` through every function.
+
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::cmp;
+
+use super::TryReserveError;
+
+#[derive(Clone, Copy)]
+struct Scope {
+    limit: usize,
+    used: usize,
+    peak: usize,
+}
+
+thread_local! {
+    static SCOPE: Cell<Option<Scope>> = const { Cell::new(None) };
+}
+
+fn current() -> Option<Scope> {
+    // During thread teardown there is no scope to consult.
+    SCOPE.try_with(Cell::get).ok().and_then(|scope| scope)
+}
+
+fn set(scope: Option<Scope>) {
+    let _ = SCOPE.try_with(|s| s.set(scope));
+}
+
+/// Runs |f| with a limit of |limit| bytes on what the crate allocates on
+/// this thread, then returns its result together with the peak number of
+/// bytes charged while it ran.
+///
+/// Allocations that would take the total past the limit fail with
+/// `TryReserveError::AllocFailed`, as do those refused by fault injection.
+///
+/// Growth through the crate's fallible methods is charged: `FallibleVec`
+/// and the types built on it, `AllocVec`, arenas and boxes, as well as
+/// the fallible `VecDeque`, hash table and `SmallVec` methods. Memory
+/// grown by other means, such as std's infallible methods, isn't seen.
+/// Collections that allocate inside std or another crate are charged for
+/// the elements they were asked to hold, which for hash tables is less
+/// than the table really allocates. This is also what fault injection
+/// and `AllocStats` see.
+///
+/// Memory that is freed or shrunk isn't credited back, since it may not
+/// have been charged to this scope in the first place, so apart from hash
+/// tables the numbers are an upper bound on what the scope allocated.
+///
+/// Scopes nest: an inner scope is also bounded by what remains of the
+/// outer one, and what it charges counts towards the outer one. The
+/// previous limit is restored when |f| returns or panics.
+pub fn with_alloc_limit<R, F: FnOnce() -> R>(limit: usize, f: F) -> (R, usize) {
+    struct Restore {
+        outer: Option<Scope>,
+    }
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let inner = current();
+            let outer = match (self.outer, inner) {
+                (Some(mut outer), Some(inner)) => {
+                    outer.peak = cmp::max(outer.peak, outer.used + inner.peak);
+                    outer.used += inner.used;
+                    Some(outer)
+                }
+                (outer, _) => outer,
+            };
+            set(outer);
+        }
+    }
+
+    let outer = current();
+    let limit = match outer {
+        Some(outer) => cmp::min(limit, outer.limit - outer.used),
+        None => limit,
+    };
+    let restore = Restore { outer };
+    set(Some(Scope { limit, used: 0, peak: 0 }));
+
+    let result = f();
+    let peak = current().map_or(0, |scope| scope.peak);
+    drop(restore);
+    (result, peak)
+}
+
+/// Charges |bytes|, for an allocation of |layout|, against the current
+/// scope, if any.
+pub(crate) fn try_charge(bytes: usize, layout: Layout) -> Result<(), TryReserveError> {
+    if let Some(mut scope) = current() {
+        if bytes > scope.limit - scope.used {
+            return Err(TryReserveError::AllocFailed { layout });
+        }
+        scope.used += bytes;
+        scope.peak = cmp::max(scope.peak, scope.used);
+        set(Some(scope));
+    }
+    Ok(())
+}
+
+/// Gives back |bytes| charged by `try_charge` for an allocation that then
+/// failed.
+pub(crate) fn release(bytes: usize) {
+    if let Some(mut scope) = current() {
+        scope.used = scope.used.saturating_sub(bytes);
+        set(Some(scope));
+    }
+}
+
+#[test]
+fn limits_vec_growth() {
+    use super::FallibleVec;
+
+    let (result, peak) = with_alloc_limit(1024, || {
+        let mut vec: Vec<u8> = FallibleVec::try_with_capacity(512)?;
+        vec.resize(512, 0);
+        FallibleVec::try_reserve_exact(&mut vec, 256)?;
+        FallibleVec::try_reserve_exact(&mut vec, 513)
+    });
+    assert!(result.is_err());
+    assert_eq!(peak, 768);
+
+    // The limit is gone once the scope exits.
+    let vec: Vec<u8> = FallibleVec::try_with_capacity(4096).unwrap();
+    assert_eq!(vec.capacity(), 4096);
+}
+
+#[test]
+fn shrinking_is_not_credited() {
+    use super::FallibleVec;
+
+    let mut outside: Vec<u8> = Vec::with_capacity(100_000);
+    outside.push(1);
+    let (result, peak) = with_alloc_limit(1000, || {
+        let _a: Vec<u8> = FallibleVec::try_with_capacity(900)?;
+        // Shrinking memory the scope never charged must not make room.
+        let _boxed = FallibleVec::try_into_boxed_slice(outside)?;
+        let _b: Vec<u8> = FallibleVec::try_with_capacity(900)?;
+        Ok::<(), TryReserveError>(())
+    });
+    assert!(result.is_err(), "1800 bytes can't be live in a 1000 byte scope");
+    assert_eq!(peak, 900);
+}
+
+#[test]
+fn nested_and_restored_on_panic() {
+    use super::FallibleVec;
+    use std::panic;
+
+    let ((), outer_peak) = with_alloc_limit(1000, || {
+        let _a: Vec<u8> = FallibleVec::try_with_capacity(600).unwrap();
+        let ((), inner_peak) = with_alloc_limit(10_000, || {
+            // Bounded by the 400 bytes left in the outer scope.
+            assert!(<Vec<u8> as FallibleVec<u8>>::try_with_capacity(500).is_err());
+            let _b: Vec<u8> = FallibleVec::try_with_capacity(300).unwrap();
+        });
+        assert_eq!(inner_peak, 300);
+
+        let caught = panic::catch_unwind(|| {
+            with_alloc_limit(10, || panic!("parser bug"))
+        });
+        assert!(caught.is_err());
+        // The outer limit is back in force.
+        assert!(<Vec<u8> as FallibleVec<u8>>::try_with_capacity(200).is_err());
+        let _c: Vec<u8> = FallibleVec::try_with_capacity(100).unwrap();
+    });
+    assert_eq!(outer_peak, 1000);
+    assert!(current().is_none());
+}
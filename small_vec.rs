@@ -0,0 +1,192 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
+use smallvec::{Array, CollectionAllocErr, SmallVec};
+
+use super::{FallibleVec, TryReserveError, try_grow_with};
+
+fn map_err(e: CollectionAllocErr) -> TryReserveError {
+    match e {
+        CollectionAllocErr::CapacityOverflow => TryReserveError::CapacityOverflow,
+        CollectionAllocErr::AllocErr { layout } => TryReserveError::AllocFailed { layout },
+    }
+}
+
+/// Makes room for |additional| more elements in |vec|, rounding the new
+/// capacity up to a power of two as `SmallVec::try_reserve` does unless
+/// |exact|. The heap buffer `SmallVec` allocates is charged through the
+/// crate's allocation hooks first, like any other growing collection.
+fn reserve<A: Array>(vec: &mut SmallVec<A>, additional: usize, exact: bool) -> Result<(), TryReserveError> {
+    if vec.capacity() - vec.len() >= additional {
+        return Ok(());
+    }
+    let required = vec.len().checked_add(additional).ok_or(TryReserveError::CapacityOverflow)?;
+    let new_cap = if exact {
+        required
+    } else {
+        required.checked_next_power_of_two().ok_or(TryReserveError::CapacityOverflow)?
+    };
+    // The inline capacity is never smaller than the current one, so this
+    // always needs a heap buffer.
+    let layout = Layout::array::<A::Item>(new_cap)
+        .map_err(|_| TryReserveError::CapacityOverflow)?;
+    let old_size = if vec.spilled() { vec.capacity() * mem::size_of::<A::Item>() } else { 0 };
+    try_grow_with(layout.size() - old_size, layout, || {
+        let result = if exact {
+            vec.try_reserve_exact(additional)
+        } else {
+            vec.try_reserve(additional)
+        };
+        result.map_err(map_err)
+    })
+}
+
+impl<A: Array> FallibleVec<A::Item> for SmallVec<A> {
+    #[inline]
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut vec = SmallVec::new();
+        reserve(&mut vec, capacity, true)?;
+        Ok(vec)
+    }
+
+    #[inline]
+    fn try_push(&mut self, value: A::Item) -> Result<(), TryReserveError> {
+        FallibleVec::try_reserve(self, 1)?;
+        self.push(value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let rounded = self.len().checked_add(additional).is_some_and(usize::is_power_of_two);
+        match reserve(self, additional, false) {
+            // Rounding up may overflow, or exceed a limit, even when the
+            // requested capacity doesn't.
+            Err(_) if !rounded => reserve(self, additional, true),
+            result => result,
+        }
+    }
+
+    #[inline]
+    fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        reserve(self, additional, true)
+    }
+
+    #[inline]
+    fn try_extend_from_slice(&mut self, other: &[A::Item]) -> Result<(), TryReserveError>
+        where A::Item: Clone
+    {
+        FallibleVec::try_reserve(self, other.len())?;
+        self.extend(other.iter().cloned());
+        Ok(())
+    }
+
+    #[inline]
+    fn try_extend<I: IntoIterator<Item = A::Item>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        let iter = iter.into_iter();
+        FallibleVec::try_reserve(self, iter.size_hint().0)?;
+        for item in iter {
+            FallibleVec::try_push(self, item)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_insert(&mut self, index: usize, value: A::Item) -> Result<(), TryReserveError> {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        FallibleVec::try_reserve(self, 1)?;
+        self.insert(index, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_resize(&mut self, new_len: usize, value: A::Item) -> Result<(), TryReserveError>
+        where A::Item: Clone
+    {
+        FallibleVec::try_resize_with(self, new_len, || value.clone())
+    }
+
+    #[inline]
+    fn try_resize_with<F: FnMut() -> A::Item>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError> {
+        if new_len > self.len() {
+            FallibleVec::try_reserve(self, new_len - self.len())?;
+        }
+        self.resize_with(new_len, f);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_into_boxed_slice(self) -> Result<Box<[A::Item]>, TryReserveError> {
+        if self.spilled() {
+            // Takes over the heap buffer without allocating.
+            return FallibleVec::try_into_boxed_slice(self.into_vec());
+        }
+        let mut vec: Vec<A::Item> = FallibleVec::try_with_capacity(self.len())?;
+        vec.extend(self);
+        Ok(vec.into_boxed_slice())
+    }
+}
+
+#[test]
+fn inline_and_spilled() {
+    let mut vec: SmallVec<[u32; 4]> = FallibleVec::try_with_capacity(2).unwrap();
+    assert!(!vec.spilled());
+    FallibleVec::try_extend(&mut vec, 0..4).unwrap();
+    assert!(!vec.spilled());
+    vec.try_push(4).unwrap();
+    assert!(vec.spilled());
+    vec.try_insert(0, 9).unwrap();
+    FallibleVec::try_resize(&mut vec, 8, 7).unwrap();
+    assert_eq!(&vec[..], [9, 0, 1, 2, 3, 4, 7, 7]);
+    assert_eq!(&*vec.try_into_boxed_slice().unwrap(), [9, 0, 1, 2, 3, 4, 7, 7]);
+
+    let mut vec: SmallVec<[u8; 8]> = SmallVec::new();
+    vec.try_extend_from_slice(b"stsd").unwrap();
+    assert_eq!(&*vec.try_into_boxed_slice().unwrap(), b"stsd");
+}
+
+#[test]
+fn oom() {
+    let mut vec: SmallVec<[u64; 2]> = SmallVec::new();
+    vec.try_push(1).unwrap();
+    assert!(FallibleVec::try_reserve(&mut vec, usize::MAX).is_err());
+    assert!(FallibleVec::try_reserve_exact(&mut vec, usize::MAX / 4).is_err());
+    assert_eq!(&vec[..], [1]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn limited() {
+    let mut vec: SmallVec<[u64; 4]> = SmallVec::new();
+    let (result, peak) = super::with_alloc_limit(256, || {
+        // Inline pushes allocate nothing.
+        FallibleVec::try_extend(&mut vec, 0..4)?;
+        FallibleVec::try_push(&mut vec, 4)?;
+        FallibleVec::try_reserve(&mut vec, 1000)
+    });
+    assert!(result.is_err(), "a thousand u64s are over the limit");
+    assert_eq!(peak, 8 * 8);
+    assert_eq!(&vec[..], [0, 1, 2, 3, 4]);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn faults_injected() {
+    use super::{FaultPolicy, FaultReport, with_fault_injection};
+
+    let mut vec: SmallVec<[u8; 4]> = SmallVec::new();
+    let (result, report) = with_fault_injection(FaultPolicy::FailAfter(0), || {
+        vec.try_extend_from_slice(b"moov")?;
+        vec.try_push(0)
+    });
+    assert!(result.is_err());
+    // Both the rounded-up and the exact capacity were tried.
+    assert_eq!(report, FaultReport { allocations: 2, injected: 2 });
+    assert_eq!(&vec[..], b"moov");
+}
@@ -0,0 +1,224 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp;
+use core::mem;
+use core::ops::{Bound, RangeBounds};
+use core::slice;
+
+use super::{FallibleVec, TryClone, TryReserveError, try_to_vec};
+
+/// An ordered map stored as a sorted `Vec` of key-value pairs, whose
+/// growth goes through `FallibleVec`.
+///
+/// `BTreeMap` allocates nodes infallibly, so this stands in for it where
+/// keys come from the file being parsed. Lookups are binary searches.
+/// Inserting in key order (the usual case for sample indices) appends in
+/// amortized constant time; inserting out of order shifts the later
+/// entries, like `Vec::insert`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortedVecMap<K, V> {
+    entries: Vec<(K, V)>,
+}
+
+/// Duplicates the map without aborting on lack of memory, unlike the
+/// derived `Clone`.
+impl<K: TryClone, V: TryClone> TryClone for SortedVecMap<K, V> {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Ok(SortedVecMap { entries: try_to_vec(&self.entries)? })
+    }
+}
+
+impl<K, V> Default for SortedVecMap<K, V> {
+    fn default() -> Self {
+        SortedVecMap::new()
+    }
+}
+
+impl<K, V> SortedVecMap<K, V> {
+    /// Creates an empty map. Doesn't allocate.
+    pub fn new() -> Self {
+        SortedVecMap { entries: Vec::new() }
+    }
+
+    /// Creates an empty map with room for at least `capacity` entries.
+    /// Returns Err(_) on lack of memory or if the capacity overflows.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        Ok(SortedVecMap { entries: FallibleVec::try_with_capacity(capacity)? })
+    }
+
+    /// Reserves capacity for at least `additional` more entries.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        FallibleVec::try_reserve(&mut self.entries, additional)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the entries in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.entries.iter() }
+    }
+
+    /// The entries in key order, as a slice.
+    pub fn as_slice(&self) -> &[(K, V)] {
+        &self.entries
+    }
+}
+
+impl<K: Ord, V> SortedVecMap<K, V> {
+    fn search<Q: ?Sized + Ord>(&self, key: &Q) -> Result<usize, usize> where K: Borrow<Q> {
+        self.entries.binary_search_by(|entry| entry.0.borrow().cmp(key))
+    }
+
+    /// Inserts a key-value pair, returning the old value if |key| was
+    /// present. Returns Err(_) if growing the storage fails, in which
+    /// case the map is unchanged.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError> {
+        match self.search(&key) {
+            Ok(index) => Ok(Some(mem::replace(&mut self.entries[index].1, value))),
+            Err(index) => {
+                FallibleVec::try_insert(&mut self.entries, index, (key, value))?;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V> where K: Borrow<Q> {
+        self.search(key).ok().map(|index| &self.entries[index].1)
+    }
+
+    pub fn get_mut<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q> {
+        match self.search(key) {
+            Ok(index) => Some(&mut self.entries[index].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool where K: Borrow<Q> {
+        self.search(key).is_ok()
+    }
+
+    /// Removes |key| from the map, returning its value if it was present.
+    /// Never allocates.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q> {
+        match self.search(key) {
+            Ok(index) => Some(self.entries.remove(index).1),
+            Err(_) => None,
+        }
+    }
+
+    /// Iterates over the entries whose keys fall in |range|, in key order,
+    /// like `BTreeMap::range`. For example `map.range(..=n).next_back()`
+    /// finds the entry with the greatest key not above `n`.
+    pub fn range<Q: ?Sized + Ord, R: RangeBounds<Q>>(&self, range: R) -> Iter<'_, K, V>
+        where K: Borrow<Q>
+    {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.entries.partition_point(|e| e.0.borrow() < start),
+            Bound::Excluded(start) => self.entries.partition_point(|e| e.0.borrow() <= start),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => self.entries.partition_point(|e| e.0.borrow() <= end),
+            Bound::Excluded(end) => self.entries.partition_point(|e| e.0.borrow() < end),
+            Bound::Unbounded => self.entries.len(),
+        };
+        let end = cmp::max(start, end);
+        Iter { inner: self.entries[start..end].iter() }
+    }
+}
+
+/// An iterator over the entries of a `SortedVecMap`, in key order.
+#[derive(Clone, Debug)]
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|entry| (&entry.0, &entry.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|entry| (&entry.0, &entry.1))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> IntoIterator for &'a SortedVecMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+#[test]
+fn insert_and_get() {
+    let mut map = SortedVecMap::new();
+    for &key in &[5u32, 1, 9, 3, 7] {
+        assert_eq!(map.try_insert(key, key * 10), Ok(None));
+    }
+    assert_eq!(map.try_insert(3, 31), Ok(Some(30)));
+    assert_eq!(map.len(), 5);
+    assert!(map.iter().map(|(k, _)| *k).eq(vec![1, 3, 5, 7, 9]));
+    assert_eq!(map.get(&3), Some(&31));
+    assert_eq!(map.get(&4), None);
+    *map.get_mut(&9).unwrap() += 1;
+    assert_eq!(map.remove(&9), Some(91));
+    assert!(!map.contains_key(&9));
+}
+
+#[test]
+fn range() {
+    let mut map = SortedVecMap::new();
+    for key in (0..100u32).step_by(10) {
+        map.try_insert(key, ()).unwrap();
+    }
+    let keys = |iter: Iter<u32, ()>| iter.map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keys(map.range(15..40)), [20, 30]);
+    assert_eq!(keys(map.range(20..=40)), [20, 30, 40]);
+    assert_eq!(keys(map.range((Bound::Excluded(80), Bound::Unbounded))), [90]);
+    assert_eq!(keys(map.range(..5)), [0]);
+    assert_eq!(keys(map.range(..0)), []);
+    assert_eq!(map.range(..=55).next_back(), Some((&50, &())));
+    assert_eq!(map.range(1000..).count(), 0);
+    assert_eq!(map.range((Bound::Included(50), Bound::Excluded(20))).count(), 0);
+}
+
+#[test]
+fn try_clone() {
+    let mut map = SortedVecMap::new();
+    map.try_insert(2u32, "b".to_string()).unwrap();
+    map.try_insert(1u32, "a".to_string()).unwrap();
+    let cloned = map.try_clone().unwrap();
+    assert_eq!(cloned, map);
+    assert_eq!(cloned.get(&1).map(String::as_str), Some("a"));
+}
+
+#[test]
+fn oom() {
+    assert!(SortedVecMap::<u32, u64>::try_with_capacity(usize::MAX).is_err());
+    let mut map = SortedVecMap::<u32, u64>::new();
+    assert!(map.try_reserve(usize::MAX).is_err(), "it should be OOM");
+    assert!(map.is_empty());
+}
@@ -0,0 +1,93 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static BYTES_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+static GROWTH_CALLS: AtomicUsize = AtomicUsize::new(0);
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+static LARGEST_REQUEST: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters for the memory the crate has asked for, across all threads,
+/// since the process started or the last `AllocStats::reset`. Only
+/// available with the `stats` feature.
+///
+/// The allocations counted are those `with_alloc_limit` sees, including
+/// requests refused by an `AllocBudget`, a limit or fault injection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Sum of the sizes, in bytes, of all requested allocations. A
+    /// reallocation counts its full new size.
+    pub bytes_requested: usize,
+    /// Number of allocations and growing reallocations requested.
+    pub growth_calls: usize,
+    /// Number of those requests that failed.
+    pub failures: usize,
+    /// Size, in bytes, of the largest single request.
+    pub largest_request: usize,
+    /// High-water mark of the bytes the crate has allocated, charged like
+    /// the peak `with_alloc_limit` reports: successful growth is added,
+    /// and memory that is freed or shrunk isn't subtracted, so this is an
+    /// upper bound on the peak in use.
+    pub peak_bytes: usize,
+}
+
+impl AllocStats {
+    /// Reads the current counters. Each counter is read atomically, but
+    /// allocations on other threads may land between the reads.
+    pub fn snapshot() -> AllocStats {
+        AllocStats {
+            bytes_requested: BYTES_REQUESTED.load(Ordering::Relaxed),
+            growth_calls: GROWTH_CALLS.load(Ordering::Relaxed),
+            failures: FAILURES.load(Ordering::Relaxed),
+            largest_request: LARGEST_REQUEST.load(Ordering::Relaxed),
+            peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sets all counters back to zero.
+    pub fn reset() {
+        BYTES_REQUESTED.store(0, Ordering::Relaxed);
+        GROWTH_CALLS.store(0, Ordering::Relaxed);
+        FAILURES.store(0, Ordering::Relaxed);
+        LARGEST_REQUEST.store(0, Ordering::Relaxed);
+        PEAK_BYTES.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Records a request for |size| bytes, of which |growth| are new memory,
+/// and whether it succeeded.
+pub(crate) fn record(size: usize, growth: usize, succeeded: bool) {
+    // Saturate rather than wrap on 32-bit targets.
+    let _ = BYTES_REQUESTED.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                                         |total| Some(total.saturating_add(size)));
+    GROWTH_CALLS.fetch_add(1, Ordering::Relaxed);
+    if succeeded {
+        let _ = PEAK_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                                        |total| Some(total.saturating_add(growth)));
+    } else {
+        FAILURES.fetch_add(1, Ordering::Relaxed);
+    }
+    LARGEST_REQUEST.fetch_max(size, Ordering::Relaxed);
+}
+
+#[test]
+fn counts_growth() {
+    use super::FallibleVec;
+
+    // Other tests allocate concurrently, so only check lower bounds.
+    let before = AllocStats::snapshot();
+    let mut vec: Vec<u8> = FallibleVec::try_with_capacity(1 << 20).unwrap();
+    vec.resize(1 << 20, 0);
+    FallibleVec::try_push(&mut vec, 1).unwrap();
+    assert!(FallibleVec::try_reserve(&mut vec, usize::MAX / 4).is_err());
+    let after = AllocStats::snapshot();
+
+    assert!(after.growth_calls >= before.growth_calls + 3);
+    assert!(after.failures > before.failures);
+    assert!(after.bytes_requested >= before.bytes_requested + (3 << 20));
+    assert!(after.largest_request >= usize::MAX / 4);
+    assert!(after.peak_bytes >= before.peak_bytes + (2 << 20));
+}
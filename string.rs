@@ -0,0 +1,107 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::string::String;
+use core::char;
+use core::str;
+
+use super::{FallibleVec, TryReserveError};
+
+/// Fallible counterparts of the growing `String` methods, built on
+/// `FallibleVec` for the underlying bytes.
+///
+/// `String::try_reserve` is an inherent method in newer versions of std,
+/// which takes precedence over the trait method, so call it as
+/// `FallibleString::try_reserve(&mut s, n)`.
+pub trait FallibleString {
+    /// Append |ch| to the end of the string. Returns Ok(()) on success,
+    /// Err(_) if it fails, which can only be due to lack of memory.
+    fn try_push(&mut self, ch: char) -> Result<(), TryReserveError>;
+
+    /// Append |s| to the end of the string. Returns Ok(()) on success,
+    /// Err(_) if it fails, which can only be due to lack of memory.
+    fn try_push_str(&mut self, s: &str) -> Result<(), TryReserveError>;
+
+    /// Reserves capacity for at least `additional` more bytes, with the
+    /// same amortized growth as `FallibleVec::try_reserve`. Returns Err(_)
+    /// on lack of memory or if the capacity overflows.
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+
+    /// Converts |v| to a string like `String::from_utf8_lossy`, replacing
+    /// invalid sequences with U+FFFD, but always returning a newly
+    /// allocated string. Returns Err(_) on lack of memory.
+    fn try_from_utf8_lossy(v: &[u8]) -> Result<Self, TryReserveError> where Self: Sized;
+}
+
+impl FallibleString for String {
+    #[inline]
+    fn try_push(&mut self, ch: char) -> Result<(), TryReserveError> {
+        let mut buf = [0; 4];
+        self.try_push_str(ch.encode_utf8(&mut buf))
+    }
+
+    #[inline]
+    fn try_push_str(&mut self, s: &str) -> Result<(), TryReserveError> {
+        // Only whole strs are appended, so the contents stay valid UTF-8.
+        FallibleVec::try_extend_from_slice(unsafe { self.as_mut_vec() }, s.as_bytes())
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        FallibleVec::try_reserve(unsafe { self.as_mut_vec() }, additional)
+    }
+
+    fn try_from_utf8_lossy(v: &[u8]) -> Result<String, TryReserveError> {
+        let mut out = String::new();
+        FallibleString::try_reserve(&mut out, v.len())?;
+        let mut input = v;
+        loop {
+            match str::from_utf8(input) {
+                Ok(valid) => {
+                    out.try_push_str(valid)?;
+                    return Ok(out);
+                }
+                Err(e) => {
+                    let (valid, after) = input.split_at(e.valid_up_to());
+                    out.try_push_str(unsafe { str::from_utf8_unchecked(valid) })?;
+                    out.try_push(char::REPLACEMENT_CHARACTER)?;
+                    match e.error_len() {
+                        Some(len) => input = &after[len..],
+                        // Truncated sequence at the end of the input.
+                        None => return Ok(out),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn try_push() {
+    let mut s = String::new();
+    s.try_push('a').unwrap();
+    s.try_push('©').unwrap();
+    s.try_push_str("nam").unwrap();
+    assert_eq!(s, "a©nam");
+
+    FallibleString::try_reserve(&mut s, 100).unwrap();
+    assert!(s.capacity() >= s.len() + 100);
+    assert!(FallibleString::try_reserve(&mut s, usize::MAX).is_err());
+    assert_eq!(s, "a©nam");
+}
+
+#[test]
+fn try_from_utf8_lossy() {
+    let cases: &[&[u8]] = &[
+        b"",
+        b"hello",
+        b"\xa9nam",
+        b"Hello\xC0\x80 There\xE6\x83 Goodbye",
+        b"trailing \xF0\x90\x80",
+        "caf\u{e9}".as_bytes(),
+    ];
+    for &bytes in cases {
+        assert_eq!(String::try_from_utf8_lossy(bytes).unwrap(), String::from_utf8_lossy(bytes));
+    }
+}
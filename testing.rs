@@ -0,0 +1,226 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Fault injection, for testing how callers handle allocation failure.
+//! Only available with the `testing` feature.
+
+use alloc::alloc::Layout;
+use core::cell::Cell;
+
+use super::TryReserveError;
+
+/// Which allocations `with_fault_injection` makes fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultPolicy {
+    /// The first N allocations succeed and every later one fails.
+    FailAfter(usize),
+    /// Allocations of more than this many bytes fail.
+    FailAbove(usize),
+    /// Each allocation fails with the given probability, drawn from a
+    /// generator seeded with `seed` so that runs are reproducible.
+    FailRandomly { seed: u64, probability: f64 },
+}
+
+/// What happened while a `with_fault_injection` closure ran.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaultReport {
+    /// Number of allocations the crate attempted.
+    pub allocations: usize,
+    /// Number of those that were made to fail.
+    pub injected: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Injector {
+    policy: FaultPolicy,
+    rng: u64,
+    report: FaultReport,
+}
+
+thread_local! {
+    static INJECTOR: Cell<Option<Injector>> = const { Cell::new(None) };
+}
+
+/// Runs |f| with |policy| deciding which allocations made by the crate on
+/// this thread fail, and reports how many were attempted and failed.
+/// Injected failures look exactly like the allocator returning null. The
+/// previous policy, if any, is restored when |f| returns or panics.
+///
+/// The allocations consulted are the ones `with_alloc_limit` sees.
+///
+/// To walk every failure point of a parser, run it with `FailAfter(n)`
+/// for increasing `n` until the report shows nothing was injected:
+///
+/// ```
+/// use mp4parse_fallible::{FallibleVec, FaultPolicy, with_fault_injection};
+///
+/// fn parse(input: &[u32]) -> Result<Vec<u32>, ()> {
+///     let mut out = Vec::new();
+///     for &n in input {
+///         FallibleVec::try_push(&mut out, n)?;
+///     }
+///     Ok(out)
+/// }
+///
+/// let input: Vec<u32> = (0..100).collect();
+/// for n in 0.. {
+///     let (result, report) = with_fault_injection(FaultPolicy::FailAfter(n), || parse(&input));
+///     if report.injected == 0 {
+///         assert_eq!(result.unwrap(), input);
+///         break;
+///     }
+///     assert!(result.is_err());
+/// }
+/// ```
+pub fn with_fault_injection<R, F: FnOnce() -> R>(policy: FaultPolicy, f: F) -> (R, FaultReport) {
+    struct Restore {
+        outer: Option<Injector>,
+    }
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let _ = INJECTOR.try_with(|i| i.set(self.outer));
+        }
+    }
+
+    let seed = match policy {
+        FaultPolicy::FailRandomly { seed, .. } => seed,
+        _ => 0,
+    };
+    let injector = Injector {
+        policy,
+        // xorshift gets stuck at zero.
+        rng: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+        report: FaultReport::default(),
+    };
+    let restore = Restore { outer: INJECTOR.with(|i| i.replace(Some(injector))) };
+
+    let result = f();
+    let report = INJECTOR.with(Cell::get).map_or(FaultReport::default(), |i| i.report);
+    drop(restore);
+    (result, report)
+}
+
+/// Consulted for every allocation; fails it if the current policy says so.
+pub(crate) fn check(layout: Layout) -> Result<(), TryReserveError> {
+    let mut injector = match INJECTOR.try_with(Cell::get) {
+        Ok(Some(injector)) => injector,
+        _ => return Ok(()),
+    };
+    let fail = match injector.policy {
+        FaultPolicy::FailAfter(n) => injector.report.allocations >= n,
+        FaultPolicy::FailAbove(size) => layout.size() > size,
+        FaultPolicy::FailRandomly { probability, .. } => {
+            // xorshift64*
+            let mut x = injector.rng;
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            injector.rng = x;
+            let sample = x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+            (sample as f64 / (1u64 << 53) as f64) < probability
+        }
+    };
+    injector.report.allocations += 1;
+    if fail {
+        injector.report.injected += 1;
+    }
+    INJECTOR.with(|i| i.set(Some(injector)));
+    if fail {
+        return Err(TryReserveError::AllocFailed { layout });
+    }
+    Ok(())
+}
+
+#[test]
+fn fail_after() {
+    use super::FallibleVec;
+
+    let (result, report) = with_fault_injection(FaultPolicy::FailAfter(2), || {
+        let mut vec: Vec<u32> = Vec::new();
+        let mut pushed = 0;
+        for i in 0..100 {
+            if FallibleVec::try_push(&mut vec, i).is_err() {
+                break;
+            }
+            pushed += 1;
+        }
+        (pushed, vec)
+    });
+    // Capacity 4, then 8, then the third allocation fails.
+    assert_eq!(result.0, 8);
+    assert_eq!(result.1.len(), 8);
+    assert_eq!(report, FaultReport { allocations: 3, injected: 1 });
+}
+
+#[test]
+fn fail_above() {
+    use super::{FallibleVec, try_box_new};
+
+    let ((), report) = with_fault_injection(FaultPolicy::FailAbove(1024), || {
+        let small: Result<Vec<u8>, _> = FallibleVec::try_with_capacity(1024);
+        assert!(small.is_ok());
+        let large: Result<Vec<u8>, _> = FallibleVec::try_with_capacity(1025);
+        match large {
+            Err(TryReserveError::AllocFailed { layout }) => assert_eq!(layout.size(), 1025),
+            r => panic!("expected an injected failure, got {:?}", r.map(|v| v.capacity())),
+        }
+        assert!(try_box_new([0u8; 2048]).is_err());
+    });
+    assert_eq!(report.injected, 2);
+
+    // No policy outside the closure.
+    let vec: Vec<u8> = FallibleVec::try_with_capacity(4096).unwrap();
+    assert_eq!(vec.capacity(), 4096);
+}
+
+#[test]
+fn fail_randomly_is_reproducible() {
+    use super::FallibleVec;
+
+    let run = |seed| {
+        let policy = FaultPolicy::FailRandomly { seed, probability: 0.5 };
+        with_fault_injection(policy, || {
+            (0..64).map(|_| {
+                <Vec<u8> as FallibleVec<u8>>::try_with_capacity(16).is_ok()
+            }).collect::<Vec<_>>()
+        })
+    };
+    let (first, report) = run(42);
+    assert_eq!(run(42).0, first);
+    assert_eq!(report.allocations, 64);
+    assert!(report.injected > 0 && report.injected < 64);
+    assert_eq!(first.iter().filter(|&&ok| !ok).count(), report.injected);
+
+    let never = FaultPolicy::FailRandomly { seed: 1, probability: 0.0 };
+    assert_eq!(with_fault_injection(never, || ()).1.injected, 0);
+}
+
+#[test]
+fn try_extend_keeps_pushed_items() {
+    use super::FallibleVec;
+
+    let (vec, report) = with_fault_injection(FaultPolicy::FailAfter(1), || {
+        let mut vec: Vec<u32> = Vec::new();
+        assert!(FallibleVec::try_extend(&mut vec, (0..100).filter(|_| true)).is_err());
+        vec
+    });
+    assert_eq!(report.injected, 1);
+    assert_eq!(vec, [0, 1, 2, 3]);
+}
+
+#[test]
+fn covers_other_collections() {
+    use super::{FallibleHashMap, FallibleVecDeque};
+    use std::collections::{HashMap, VecDeque};
+
+    let mut deque: VecDeque<u32> = VecDeque::new();
+    let mut map: HashMap<u32, u32> = HashMap::new();
+    let ((), report) = with_fault_injection(FaultPolicy::FailAfter(0), || {
+        assert!(deque.try_push_back(1).is_err());
+        assert!(FallibleHashMap::try_insert(&mut map, 1, 1).is_err());
+    });
+    assert_eq!(report, FaultReport { allocations: 2, injected: 2 });
+    assert!(deque.is_empty() && map.is_empty());
+}
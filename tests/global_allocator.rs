@@ -0,0 +1,113 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks that every allocation made by `FallibleVec` goes through the
+//! application's `#[global_allocator]`, so that memory grown by this
+//! crate can be freed by `Vec`'s `Drop` and vice versa.
+
+extern crate mp4parse_fallible;
+
+use mp4parse_fallible::FallibleVec;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+// Counted per thread so allocations made by the test harness on other
+// threads don't disturb the balance.
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+    static REALLOCS: Cell<usize> = const { Cell::new(0) };
+    static FREES: Cell<usize> = const { Cell::new(0) };
+    static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+fn bump(counter: &'static std::thread::LocalKey<Cell<usize>>) {
+    let _ = counter.try_with(|c| c.set(c.get() + 1));
+}
+
+fn adjust_live(delta: isize) {
+    let _ = LIVE_BYTES.try_with(|c| c.set(c.get() + delta));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            bump(&ALLOCS);
+            adjust_live(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        bump(&FREES);
+        adjust_live(-(layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            bump(&REALLOCS);
+            adjust_live(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn counts() -> (usize, usize, usize, isize) {
+    (ALLOCS.with(Cell::get), REALLOCS.with(Cell::get), FREES.with(Cell::get), LIVE_BYTES.with(Cell::get))
+}
+
+#[test]
+fn allocations_pair_up() {
+    let (allocs_before, reallocs_before, frees_before, live_before) = counts();
+
+    {
+        let mut vec: Vec<u32> = Vec::new();
+        for i in 0..1000 {
+            FallibleVec::try_push(&mut vec, i).unwrap();
+        }
+        FallibleVec::try_reserve(&mut vec, 10_000).unwrap();
+        FallibleVec::try_extend_from_slice(&mut vec, &[1, 2, 3]).unwrap();
+
+        // A Vec allocated by std and grown by us, then grown by std again.
+        let mut mixed: Vec<u8> = Vec::with_capacity(1);
+        FallibleVec::try_extend_from_slice(&mut mixed, b"hello world").unwrap();
+        mixed.reserve(4096);
+
+        let (allocs, reallocs, _, live) = counts();
+        assert!(allocs > allocs_before, "growth must go through the global allocator");
+        assert!(reallocs > reallocs_before, "regrowth must use the global realloc");
+        assert!(live > live_before);
+    }
+
+    let (allocs_after, _, frees_after, live_after) = counts();
+    assert_eq!(allocs_after - allocs_before, frees_after - frees_before);
+    assert_eq!(live_after, live_before);
+}
+
+#[test]
+fn arena_frees_everything_on_drop() {
+    use mp4parse_fallible::FallibleArena;
+
+    let (allocs_before, _, frees_before, live_before) = counts();
+    {
+        let arena = FallibleArena::new();
+        let mut vec = arena.vec();
+        for i in 0..10_000u32 {
+            vec.try_push(i).unwrap();
+            arena.try_alloc(i).unwrap();
+        }
+        arena.try_alloc_slice_copy(&[0u8; 100_000]).unwrap();
+        assert!(counts().3 > live_before);
+    }
+    let (allocs_after, _, frees_after, live_after) = counts();
+    assert_eq!(allocs_after - allocs_before, frees_after - frees_before);
+    assert_eq!(live_after, live_before);
+}
@@ -0,0 +1,130 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Fallible cloning.
+//!
+//! `Clone::clone` aborts if an allocation fails, so duplicating a parsed
+//! structure with large sample tables isn't safe during parsing.
+//! `TryClone` is the fallible equivalent. It is implemented for the
+//! primitive types and recursively for the containers below, so nested
+//! types such as `Vec<Vec<u32>>` or `Option<Box<[u8]>>` work as is. Your
+//! own types implement it field by field:
+//!
+//! ```
+//! use mp4parse_fallible::{TryClone, TryReserveError};
+//!
+//! struct Track {
+//!     id: u32,
+//!     sample_sizes: Vec<u32>,
+//!     name: Option<String>,
+//! }
+//!
+//! impl TryClone for Track {
+//!     fn try_clone(&self) -> Result<Self, TryReserveError> {
+//!         Ok(Track {
+//!             id: self.id,
+//!             sample_sizes: self.sample_sizes.try_clone()?,
+//!             name: self.name.try_clone()?,
+//!         })
+//!     }
+//! }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{FallibleVec, TryReserveError};
+
+pub trait TryClone: Sized {
+    /// Returns a copy of the value. Returns Err(_) if it fails, which
+    /// can only be due to lack of memory.
+    fn try_clone(&self) -> Result<Self, TryReserveError>;
+}
+
+/// Clones all elements of |slice| into a new Vec, allocating fallibly.
+pub fn try_to_vec<T: TryClone>(slice: &[T]) -> Result<Vec<T>, TryReserveError> {
+    let mut vec: Vec<T> = FallibleVec::try_with_capacity(slice.len())?;
+    for item in slice {
+        // The capacity was reserved above, so this never reallocates.
+        vec.push(item.try_clone()?);
+    }
+    Ok(vec)
+}
+
+macro_rules! impl_try_clone_for_copy {
+    ($($t:ty)*) => {
+        $(
+            impl TryClone for $t {
+                #[inline]
+                fn try_clone(&self) -> Result<Self, TryReserveError> {
+                    Ok(*self)
+                }
+            }
+        )*
+    }
+}
+
+impl_try_clone_for_copy! {
+    () bool char
+    u8 u16 u32 u64 u128 usize
+    i8 i16 i32 i64 i128 isize
+    f32 f64
+}
+
+impl<T: TryClone> TryClone for Vec<T> {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        try_to_vec(self)
+    }
+}
+
+impl<T: TryClone> TryClone for Box<[T]> {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        // try_to_vec allocates exactly |len| elements, so this doesn't
+        // reallocate.
+        Ok(try_to_vec(self)?.into_boxed_slice())
+    }
+}
+
+impl TryClone for String {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        let mut bytes: Vec<u8> = FallibleVec::try_with_capacity(self.len())?;
+        bytes.extend_from_slice(self.as_bytes());
+        // The bytes were copied from a str, so they are valid UTF-8.
+        Ok(unsafe { String::from_utf8_unchecked(bytes) })
+    }
+}
+
+impl<T: TryClone> TryClone for Option<T> {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        match *self {
+            Some(ref value) => Ok(Some(value.try_clone()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<A: TryClone, B: TryClone> TryClone for (A, B) {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Ok((self.0.try_clone()?, self.1.try_clone()?))
+    }
+}
+
+#[test]
+fn try_clone_nested() {
+    let vec = vec![vec![1u32, 2], vec![], vec![3]];
+    let cloned = vec.try_clone().unwrap();
+    assert_eq!(cloned, vec);
+    assert_ne!(cloned.as_ptr(), vec.as_ptr());
+
+    let boxed: Box<[Option<String>]> = vec![Some("©nam".to_string()), None].into_boxed_slice();
+    assert_eq!(boxed.try_clone().unwrap(), boxed);
+}
+
+#[test]
+fn try_clone_slice() {
+    let data = [1u8, 2, 3];
+    assert_eq!(try_to_vec(&data[1..]).unwrap(), [2, 3]);
+    assert!(try_to_vec::<u8>(&[]).unwrap().is_empty());
+}
@@ -0,0 +1,148 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use alloc::alloc::Layout;
+use core::cmp;
+use core::mem;
+use alloc::collections::VecDeque;
+
+use super::{TryReserveError, try_grow_with};
+
+/// Fallible counterparts of the growing `VecDeque` methods.
+///
+/// `VecDeque::try_reserve` is an inherent method in newer versions of
+/// std, which takes precedence over the trait method, so call it as
+/// `FallibleVecDeque::try_reserve(&mut deque, n)`.
+pub trait FallibleVecDeque<T> {
+    /// Appends |value| to the back of the deque. Returns Err(_) if it
+    /// fails, which can only be due to lack of memory.
+    fn try_push_back(&mut self, value: T) -> Result<(), TryReserveError>;
+
+    /// Prepends |value| to the front of the deque. Returns Err(_) if it
+    /// fails, which can only be due to lack of memory.
+    fn try_push_front(&mut self, value: T) -> Result<(), TryReserveError>;
+
+    /// Reserves capacity for at least `additional` more elements. Returns
+    /// Err(_) on lack of memory or if the capacity overflows.
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+
+    /// Appends all items produced by |iter| to the back of the deque,
+    /// reserving its lower `size_hint` up front. On failure the items
+    /// appended so far are kept and the rest of the iterator is not
+    /// consumed.
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError>;
+}
+
+impl<T> FallibleVecDeque<T> for VecDeque<T> {
+    #[inline]
+    fn try_push_back(&mut self, value: T) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_reserve(self, 1)?;
+        self.push_back(value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_push_front(&mut self, value: T) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_reserve(self, 1)?;
+        self.push_front(value);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if self.capacity() - self.len() >= additional {
+            return Ok(());
+        }
+        let required = self.len().checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        // Grow geometrically, like FallibleVec::try_reserve, so pushing
+        // one element at a time has amortized constant cost.
+        let new_cap = cmp::max(required, self.capacity().saturating_mul(2));
+        match grow_to(self, new_cap) {
+            Err(_) if new_cap > required => grow_to(self, required),
+            result => result,
+        }
+    }
+
+    #[inline]
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        let iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        FallibleVecDeque::try_reserve(self, lower)?;
+        for item in iter {
+            FallibleVecDeque::try_push_back(self, item)?;
+        }
+        Ok(())
+    }
+}
+
+/// Grows |deque| to hold |new_cap| elements, charging the growth through
+/// the crate's allocation hooks like any other growing collection.
+fn grow_to<T>(deque: &mut VecDeque<T>, new_cap: usize) -> Result<(), TryReserveError> {
+    let layout = Layout::array::<T>(new_cap)
+        .map_err(|_| TryReserveError::CapacityOverflow)?;
+    let growth = layout.size() - deque.capacity() * mem::size_of::<T>();
+    try_grow_with(growth, layout, || {
+        // std handles the wrapped-around contents when it moves them into
+        // the new buffer.
+        VecDeque::try_reserve_exact(deque, new_cap - deque.len())
+            .map_err(|_| TryReserveError::AllocFailed { layout })
+    })
+}
+
+#[test]
+fn push_both_ends() {
+    let mut deque = VecDeque::new();
+    for i in 0..10 {
+        deque.try_push_back(i).unwrap();
+        deque.try_push_front(-i - 1).unwrap();
+    }
+    assert!(deque.iter().cloned().eq(-10..10));
+}
+
+#[test]
+fn wraparound_after_growth() {
+    let mut deque: VecDeque<u32> = VecDeque::with_capacity(8);
+    let cap = deque.capacity();
+    deque.extend(0..cap as u32);
+    // Move the head into the middle of the buffer so the contents wrap.
+    for _ in 0..cap / 2 {
+        deque.pop_front();
+    }
+    let first = cap as u32 / 2;
+    for i in 0..cap as u32 / 2 {
+        deque.try_push_back(cap as u32 + i).unwrap();
+    }
+    let (front, back) = deque.as_slices();
+    assert!(!front.is_empty() && !back.is_empty(), "contents should wrap");
+
+    let next = deque.back().unwrap() + 1;
+    deque.try_push_back(next).unwrap();
+    deque.try_extend(next + 1..4 * cap as u32).unwrap();
+    FallibleVecDeque::try_reserve(&mut deque, 1000).unwrap();
+    assert!(deque.capacity() >= deque.len() + 1000);
+    assert!(deque.iter().cloned().eq(first..4 * cap as u32));
+}
+
+#[test]
+fn oom() {
+    let mut deque: VecDeque<u64> = VecDeque::new();
+    deque.try_push_back(1).unwrap();
+    assert!(FallibleVecDeque::try_reserve(&mut deque, usize::MAX).is_err(), "it should be OOM");
+    assert!(deque.try_extend(0..usize::MAX as u64).is_err());
+    assert_eq!(deque.len(), 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn limited() {
+    let mut deque: VecDeque<u64> = VecDeque::new();
+    let (result, peak) = super::with_alloc_limit(1024, || {
+        deque.try_extend(0..100)?;
+        FallibleVecDeque::try_reserve(&mut deque, 1 << 20)
+    });
+    assert!(result.is_err(), "a million u64s are over the limit");
+    assert!((800..=1024).contains(&peak));
+    assert_eq!(deque.len(), 100);
+}